@@ -0,0 +1,201 @@
+//! Derive macros for serde-rmpv.
+//!
+//! These are re-exported by `serde_rmpv` with its `derive` feature, which is
+//! on by default.
+
+use proc_macro::TokenStream;
+use proc_macro2::TokenStream as TokenStream2;
+use quote::quote;
+use syn::{parse_macro_input, parse_quote, spanned::Spanned, Data, DeriveInput, Expr, Fields};
+
+/// Derives `Serialize` and `Deserialize` for a type stored as a MessagePack
+/// Ext value, through the `_ExtStruct` convention.
+///
+/// The tag is given with `#[msgpack_ext(tag = N)]`, and must be in the `i8`
+/// range. By default the type must be a struct with a single field holding
+/// the Ext data, such as `Vec<u8>` or `[u8; N]`: any type that implements
+/// `AsRef<[u8]>` and `TryFrom<&[u8]>`. Other types supply their own encoding
+/// with `encode = "path"` and `decode = "path"`, naming functions of type
+/// `fn(&Self) -> impl AsRef<[u8]>` and `fn(&[u8]) -> Result<Self, E>` with
+/// `E: Display`.
+///
+/// # Example
+/// ```rust,ignore
+/// use serde_rmpv::MsgpackExt;
+///
+/// #[derive(MsgpackExt)]
+/// #[msgpack_ext(tag = 12)]
+/// struct Hash([u8; 32]);
+///
+/// #[derive(MsgpackExt)]
+/// #[msgpack_ext(tag = -12, encode = "Point::encode", decode = "Point::decode")]
+/// struct Point {
+///     x: u8,
+///     y: u8,
+/// }
+/// ```
+#[proc_macro_derive(MsgpackExt, attributes(msgpack_ext))]
+pub fn derive_msgpack_ext(input: TokenStream) -> TokenStream {
+    let input = parse_macro_input!(input as DeriveInput);
+    expand(input)
+        .unwrap_or_else(syn::Error::into_compile_error)
+        .into()
+}
+
+/// The options given in `#[msgpack_ext(...)]`.
+#[derive(Default)]
+struct Options {
+    tag: Option<i8>,
+    encode: Option<syn::ExprPath>,
+    decode: Option<syn::ExprPath>,
+}
+
+fn parse_options(input: &DeriveInput) -> syn::Result<Options> {
+    let mut options = Options::default();
+    for attr in input
+        .attrs
+        .iter()
+        .filter(|a| a.path().is_ident("msgpack_ext"))
+    {
+        attr.parse_nested_meta(|meta| {
+            if meta.path.is_ident("tag") {
+                let expr: Expr = meta.value()?.parse()?;
+                options.tag = Some(parse_tag(&expr)?);
+            } else if meta.path.is_ident("encode") {
+                let path: syn::LitStr = meta.value()?.parse()?;
+                options.encode = Some(path.parse()?);
+            } else if meta.path.is_ident("decode") {
+                let path: syn::LitStr = meta.value()?.parse()?;
+                options.decode = Some(path.parse()?);
+            } else {
+                return Err(meta.error("expected `tag`, `encode` or `decode`"));
+            }
+            Ok(())
+        })?;
+    }
+    Ok(options)
+}
+
+// An integer literal, possibly negated, that fits in an i8.
+fn parse_tag(expr: &Expr) -> syn::Result<i8> {
+    let (lit, negative) = match expr {
+        Expr::Lit(syn::ExprLit {
+            lit: syn::Lit::Int(lit),
+            ..
+        }) => (lit, false),
+        Expr::Unary(syn::ExprUnary {
+            op: syn::UnOp::Neg(_),
+            expr,
+            ..
+        }) => match &**expr {
+            Expr::Lit(syn::ExprLit {
+                lit: syn::Lit::Int(lit),
+                ..
+            }) => (lit, true),
+            _ => return Err(syn::Error::new(expr.span(), "expected an integer tag")),
+        },
+        _ => return Err(syn::Error::new(expr.span(), "expected an integer tag")),
+    };
+    let value = lit.base10_parse::<i128>()?;
+    let value = if negative { -value } else { value };
+    i8::try_from(value).map_err(|_| {
+        syn::Error::new(
+            expr.span(),
+            format!("ext tag {} is out of range, expected -128..=127", value),
+        )
+    })
+}
+
+fn expand(input: DeriveInput) -> syn::Result<TokenStream2> {
+    let options = parse_options(&input)?;
+    let name = &input.ident;
+    let tag = options.tag.ok_or_else(|| {
+        syn::Error::new(
+            name.span(),
+            "missing ext tag, expected #[msgpack_ext(tag = N)]",
+        )
+    })?;
+
+    let (encode, decode) = match (options.encode, options.decode) {
+        (Some(encode), Some(decode)) => (
+            quote!(&#encode(self)),
+            quote!(#decode(&data).map_err(<__D::Error as private::serde::de::Error>::custom)),
+        ),
+        (None, None) => derived_encoding(&input)?,
+        _ => {
+            return Err(syn::Error::new(
+                name.span(),
+                "`encode` and `decode` must be given together",
+            ))
+        }
+    };
+
+    let (impl_generics, ty_generics, where_clause) = input.generics.split_for_impl();
+    let mut de_generics = input.generics.clone();
+    de_generics.params.insert(0, parse_quote!('de));
+    let (de_impl_generics, _, _) = de_generics.split_for_impl();
+
+    Ok(quote! {
+        const _: () = {
+            use ::serde_rmpv::__private as private;
+
+            impl #impl_generics private::serde::Serialize for #name #ty_generics #where_clause {
+                fn serialize<__S>(&self, serializer: __S) -> ::core::result::Result<__S::Ok, __S::Error>
+                where
+                    __S: private::serde::Serializer,
+                {
+                    private::serialize_ext(
+                        serializer,
+                        #tag,
+                        ::core::convert::AsRef::<[u8]>::as_ref(#encode),
+                    )
+                }
+            }
+
+            impl #de_impl_generics private::serde::Deserialize<'de> for #name #ty_generics #where_clause {
+                fn deserialize<__D>(deserializer: __D) -> ::core::result::Result<Self, __D::Error>
+                where
+                    __D: private::serde::Deserializer<'de>,
+                {
+                    let data = private::deserialize_ext_data(deserializer, #tag)?;
+                    #decode
+                }
+            }
+        };
+    })
+}
+
+// The encoding of a struct with a single field holding the Ext data.
+fn derived_encoding(input: &DeriveInput) -> syn::Result<(TokenStream2, TokenStream2)> {
+    let error = || {
+        syn::Error::new(
+            input.ident.span(),
+            "MsgpackExt without `encode` and `decode` requires a struct with a single field",
+        )
+    };
+    let fields = match &input.data {
+        Data::Struct(s) => &s.fields,
+        _ => return Err(error()),
+    };
+    let field = match fields.iter().collect::<Vec<_>>().as_slice() {
+        [field] => *field,
+        _ => return Err(error()),
+    };
+    let ty = &field.ty;
+    let (access, construct) = match (&field.ident, fields) {
+        (Some(ident), _) => (quote!(#ident), quote!(Self { #ident: field })),
+        (None, Fields::Unnamed(_)) => {
+            let index = syn::Index::from(0);
+            (quote!(#index), quote!(Self(field)))
+        }
+        _ => return Err(error()),
+    };
+    Ok((
+        quote!(&self.#access),
+        quote! {
+            <#ty as ::core::convert::TryFrom<&[u8]>>::try_from(&data[..])
+                .map(|field| #construct)
+                .map_err(<__D::Error as private::serde::de::Error>::custom)
+        },
+    ))
+}
@@ -0,0 +1,306 @@
+use crate::ext::ExtRegistry;
+
+/// How enum variants are represented as `rmpv::Value`.
+///
+/// The deserializer detects the representation from the shape of the input,
+/// so values written with any of these can be read back regardless of the
+/// configured representation.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum EnumRepr {
+    /// Unit variants are a bare variant name, all other variants are
+    /// `[ENUM_NAME, VARIANT_NAME, ... DATA ...]`. Struct variant fields are
+    /// held in a trailing map.
+    #[default]
+    NamedArray,
+    /// Unit variants are a bare variant name, all other variants are a
+    /// single-entry map `{VARIANT_NAME: DATA}`, as produced by serde_json and
+    /// rmp-serde. Tuple variant fields are held in an array.
+    ExternallyTagged,
+    /// Like [`EnumRepr::ExternallyTagged`], but keyed by the variant index:
+    /// unit variants are a bare index, all other variants `{INDEX: DATA}`.
+    VariantIndex,
+    /// Every variant is an array headed by its variant index:
+    /// `[INDEX, ... DATA ...]`.
+    IndexArray,
+}
+
+/// How structs are represented as `rmpv::Value`.
+///
+/// The deserializer accepts any of these forms regardless of the configured
+/// representation.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum StructRepr {
+    /// A map keyed by field name: `{FIELD_NAME: VALUE, ...}`. Struct variant
+    /// fields are held in the same form.
+    #[default]
+    Map,
+    /// An array of field values in declaration order, as produced by
+    /// rmp-serde: `[VALUE, ...]`. Fields skipped with `skip_serializing_if`
+    /// are written as nil, so that later fields keep their position, unless
+    /// [`Config::with_omit_skipped_fields`] is set. The nil only reads back
+    /// into fields that accept it, such as `Option` fields; a skipped
+    /// `Vec` or other non-optional field can't be read back from this form.
+    ///
+    /// Fields marked only `skip_serializing` or only `skip_deserializing` are
+    /// not supported: serde hides them from one side, so the serializer and
+    /// deserializer count positions differently and values are read into the
+    /// wrong fields. `#[serde(skip)]` is fine.
+    Array,
+    /// A map keyed by field index in declaration order:
+    /// `{INDEX: VALUE, ...}`. Fields skipped with `skip_serializing_if` are
+    /// left out.
+    ///
+    /// As with [`StructRepr::Array`], fields marked only `skip_serializing` or
+    /// only `skip_deserializing` shift the indices of later fields, and are
+    /// not supported.
+    FieldIndex,
+}
+
+/// How the deserializer narrows MessagePack integers into smaller Rust integer
+/// types.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum IntegerPolicy {
+    /// Values that don't fit the target type are an
+    /// [`ErrorKind::IntegerOutOfRange`](crate::ErrorKind::IntegerOutOfRange).
+    #[default]
+    Checked,
+    /// Values are truncated to the target type, as with an `as` cast.
+    Wrapping,
+}
+
+/// How the deserializer handles a map with the same key more than once.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum DuplicateKeyPolicy {
+    /// Every entry is passed to the target in order. Maps keep the last
+    /// value, and derived structs report a duplicate field.
+    #[default]
+    Allow,
+    /// A duplicate key is an
+    /// [`ErrorKind::DuplicateKey`](crate::ErrorKind::DuplicateKey).
+    Error,
+    /// The first entry with a given key is used, and later ones are skipped.
+    FirstWins,
+    /// The last entry with a given key is used, and earlier ones are skipped.
+    LastWins,
+}
+
+/// How `i128` and `u128` values that don't fit a MessagePack integer are
+/// represented. Values in the `i64` or `u64` range are written as
+/// `Value::Integer`, except with [`Int128Fallback::AlwaysBinary`].
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum Int128Fallback {
+    /// Serializing an out-of-range value is an error.
+    #[default]
+    Error,
+    /// The value is written as 16 big-endian bytes in a `Value::Binary`.
+    Binary,
+    /// The value is written as 16 big-endian bytes in a `Value::Ext` with the
+    /// given tag.
+    Ext(i8),
+    /// Every value, including those that fit a MessagePack integer, is
+    /// written as 16 big-endian bytes in a `Value::Binary`, as rmp-serde does.
+    AlwaysBinary,
+}
+
+/// How unit structs such as `struct Unit;` are represented. The deserializer
+/// accepts either form.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum UnitStructRepr {
+    /// Nil, the same as `()`.
+    #[default]
+    Nil,
+    /// An empty array, as rmp-serde writes them.
+    EmptyArray,
+}
+
+/// Options controlling how values are mapped to and from `rmpv::Value`.
+///
+/// A configuration is used for every value nested in the one being
+/// serialized or deserialized. Options are set with the `with_*` builders,
+/// starting from the defaults or from a preset such as
+/// [`Config::rmp_serde_compat`].
+///
+/// # Example
+/// ```rust
+/// use serde_rmpv::{Config, EnumRepr, StructRepr};
+///
+/// let config = Config::new().with_enum_repr(EnumRepr::ExternallyTagged);
+/// let compat = Config::rmp_serde_compat().with_struct_repr(StructRepr::Map);
+///
+/// let value = serde_rmpv::to_value_with(&Some(1), &config).unwrap();
+/// let back: Option<u8> = serde_rmpv::from_value_with(&value, &compat).unwrap();
+/// assert_eq!(Some(1), back);
+/// ```
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Config {
+    pub(crate) enum_repr: EnumRepr,
+    pub(crate) struct_repr: StructRepr,
+    pub(crate) integer_policy: IntegerPolicy,
+    pub(crate) duplicate_key_policy: DuplicateKeyPolicy,
+    pub(crate) int128_fallback: Int128Fallback,
+    pub(crate) unit_struct_repr: UnitStructRepr,
+    pub(crate) omit_skipped_fields: bool,
+    pub(crate) human_readable: bool,
+    pub(crate) strict: bool,
+    pub(crate) ext_registry: ExtRegistry,
+}
+
+impl Config {
+    /// Creates a configuration with default options.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// A configuration producing the same values as rmp-serde's default
+    /// encoding: structs are arrays that leave out skipped fields, enums are
+    /// externally tagged, unit structs are empty arrays, and 128-bit integers
+    /// are 16 bytes of binary. Setting [`StructRepr::Map`] matches
+    /// rmp-serde's named encoding instead.
+    pub fn rmp_serde_compat() -> Self {
+        Self::new()
+            .with_enum_repr(EnumRepr::ExternallyTagged)
+            .with_struct_repr(StructRepr::Array)
+            .with_omit_skipped_fields(true)
+            .with_unit_struct_repr(UnitStructRepr::EmptyArray)
+            .with_int128_fallback(Int128Fallback::AlwaysBinary)
+    }
+
+    /// A configuration for the smallest output: structs are arrays and enum
+    /// variants are identified by index.
+    pub fn compact() -> Self {
+        Self::new()
+            .with_enum_repr(EnumRepr::VariantIndex)
+            .with_struct_repr(StructRepr::Array)
+    }
+
+    /// Sets the enum representation used when serializing.
+    pub fn with_enum_repr(mut self, repr: EnumRepr) -> Self {
+        self.enum_repr = repr;
+        self
+    }
+
+    /// The enum representation used when serializing.
+    pub fn enum_repr(&self) -> EnumRepr {
+        self.enum_repr
+    }
+
+    /// Sets the struct representation used when serializing.
+    pub fn with_struct_repr(mut self, repr: StructRepr) -> Self {
+        self.struct_repr = repr;
+        self
+    }
+
+    /// The struct representation used when serializing.
+    pub fn struct_repr(&self) -> StructRepr {
+        self.struct_repr
+    }
+
+    /// Sets how integers are narrowed when deserializing.
+    pub fn with_integer_policy(mut self, policy: IntegerPolicy) -> Self {
+        self.integer_policy = policy;
+        self
+    }
+
+    /// How integers are narrowed when deserializing.
+    pub fn integer_policy(&self) -> IntegerPolicy {
+        self.integer_policy
+    }
+
+    /// Sets how maps with duplicate keys are handled when deserializing.
+    /// Keys that refer to a struct field, whether by name as a string or
+    /// binary or by index, count as the field's name.
+    pub fn with_duplicate_key_policy(mut self, policy: DuplicateKeyPolicy) -> Self {
+        self.duplicate_key_policy = policy;
+        self
+    }
+
+    /// How maps with duplicate keys are handled when deserializing.
+    pub fn duplicate_key_policy(&self) -> DuplicateKeyPolicy {
+        self.duplicate_key_policy
+    }
+
+    /// Sets how 128-bit integers outside the 64-bit range are represented.
+    pub fn with_int128_fallback(mut self, fallback: Int128Fallback) -> Self {
+        self.int128_fallback = fallback;
+        self
+    }
+
+    /// How 128-bit integers outside the 64-bit range are represented.
+    pub fn int128_fallback(&self) -> Int128Fallback {
+        self.int128_fallback
+    }
+
+    /// Sets how unit structs are represented when serializing.
+    pub fn with_unit_struct_repr(mut self, repr: UnitStructRepr) -> Self {
+        self.unit_struct_repr = repr;
+        self
+    }
+
+    /// How unit structs are represented when serializing.
+    pub fn unit_struct_repr(&self) -> UnitStructRepr {
+        self.unit_struct_repr
+    }
+
+    /// Sets whether structs written as arrays leave out fields skipped with
+    /// `skip_serializing_if`, as rmp-serde does, rather than writing nil in
+    /// their place. Later fields then move up, so such structs can only be
+    /// read back when the skipped fields come last and have defaults.
+    pub fn with_omit_skipped_fields(mut self, omit: bool) -> Self {
+        self.omit_skipped_fields = omit;
+        self
+    }
+
+    /// Whether structs written as arrays leave out skipped fields.
+    pub fn omit_skipped_fields(&self) -> bool {
+        self.omit_skipped_fields
+    }
+
+    /// Sets whether the serializer and deserializer report themselves as
+    /// human-readable. This is off by default, so that types such as
+    /// `IpAddr` use their compact forms rather than strings.
+    pub fn with_human_readable(mut self, human_readable: bool) -> Self {
+        self.human_readable = human_readable;
+        self
+    }
+
+    /// Whether the serializer and deserializer report themselves as
+    /// human-readable.
+    pub fn human_readable(&self) -> bool {
+        self.human_readable
+    }
+
+    /// Sets whether the deserializer rejects input that it otherwise
+    /// tolerates: tuples and tuple variants of the wrong length, arrays with
+    /// elements left over after the target has been read, a `[ENUM, VARIANT,
+    /// ..]` enum whose name differs from the Rust type's, and struct keys
+    /// that are neither strings nor field indices.
+    pub fn with_strict(mut self, strict: bool) -> Self {
+        self.strict = strict;
+        self
+    }
+
+    /// Whether the deserializer rejects input that it otherwise tolerates.
+    pub fn strict(&self) -> bool {
+        self.strict
+    }
+
+    /// Sets the registry of types written as Ext values.
+    pub fn with_ext_registry(mut self, registry: ExtRegistry) -> Self {
+        self.ext_registry = registry;
+        self
+    }
+
+    /// The registry of types written as Ext values.
+    pub fn ext_registry(&self) -> &ExtRegistry {
+        &self.ext_registry
+    }
+
+    // This configuration without its Ext registry, for handling the plain
+    // serialized form of a registered type.
+    pub(crate) fn without_ext_registry(&self) -> Config {
+        Config {
+            ext_registry: ExtRegistry::default(),
+            ..self.clone()
+        }
+    }
+}
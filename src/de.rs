@@ -1,44 +1,359 @@
+use std::{
+    borrow::Cow,
+    collections::{hash_map::Entry, HashMap},
+};
+
 use serde::{
-    de::{self, DeserializeSeed, MapAccess, SeqAccess, Unexpected, Visitor},
+    de::{self, DeserializeOwned, DeserializeSeed, MapAccess, SeqAccess, Visitor},
     forward_to_deserialize_any, Deserialize,
 };
 
-use crate::error::*;
+use crate::{
+    config::{Config, DuplicateKeyPolicy, Int128Fallback, IntegerPolicy},
+    error::*,
+    lazy::LAZY_VALUE_STRUCT_NAME,
+    MSGPACK_EXT_STRUCT_NAME,
+};
+
+/// The input a `Deserializer` reads from. Borrowed input (`&'de rmpv::Value`)
+/// lends its strings and buffers to the target, owned input (`rmpv::Value`)
+/// moves them into it.
+trait Input<'de>: Sized {
+    type Bytes: InputBytes<'de>;
+    type Items: ExactSizeIterator<Item = Self>;
+    type Entries: ExactSizeIterator<Item = (Self, Self)>;
+
+    fn value(&self) -> &rmpv::Value;
+
+    // Visit a String or Binary as a string, falling back to bytes when the
+    // data is not valid UTF-8.
+    fn visit_str<V: Visitor<'de>>(self, visitor: V) -> RResult<V::Value>;
+
+    // Visit a Binary or String as bytes.
+    fn visit_bytes<V: Visitor<'de>>(self, visitor: V) -> RResult<V::Value>;
+
+    // The following hand the input back if it is of a different type.
+    fn into_seq(self) -> Result<Self::Items, Self>;
+    fn into_map(self) -> Result<Self::Entries, Self>;
+    fn into_ext(self) -> Result<(i8, Self::Bytes), Self>;
+}
+
+/// A byte buffer taken from the input. Borrowed buffers are lent to the
+/// visitor for `'de`, so `&'de [u8]` targets don't allocate.
+trait InputBytes<'de>: AsRef<[u8]> {
+    fn visit<V: Visitor<'de>>(self, visitor: V) -> RResult<V::Value>;
+}
+
+impl<'de> InputBytes<'de> for &'de [u8] {
+    fn visit<V: Visitor<'de>>(self, visitor: V) -> RResult<V::Value> {
+        visitor.visit_borrowed_bytes(self)
+    }
+}
+
+impl<'de> InputBytes<'de> for Vec<u8> {
+    fn visit<V: Visitor<'de>>(self, visitor: V) -> RResult<V::Value> {
+        visitor.visit_byte_buf(self)
+    }
+}
+
+type MapRefIter<'de> = std::iter::Map<
+    std::slice::Iter<'de, (rmpv::Value, rmpv::Value)>,
+    fn(&'de (rmpv::Value, rmpv::Value)) -> (&'de rmpv::Value, &'de rmpv::Value),
+>;
+
+impl<'de> Input<'de> for &'de rmpv::Value {
+    type Bytes = &'de [u8];
+    type Items = std::slice::Iter<'de, rmpv::Value>;
+    type Entries = MapRefIter<'de>;
+
+    fn value(&self) -> &rmpv::Value {
+        self
+    }
+
+    fn visit_str<V: Visitor<'de>>(self, visitor: V) -> RResult<V::Value> {
+        match self {
+            rmpv::Value::String(s) => match s.as_str() {
+                Some(s) => visitor.visit_borrowed_str(s),
+                None => s.as_bytes().visit(visitor),
+            },
+            rmpv::Value::Binary(b) => b.as_slice().visit(visitor),
+            _ => Err(Error::type_error("string", self.value())),
+        }
+    }
+
+    fn visit_bytes<V: Visitor<'de>>(self, visitor: V) -> RResult<V::Value> {
+        self.as_slice()
+            .ok_or_else(|| Error::type_error("binary", self))?
+            .visit(visitor)
+    }
+
+    fn into_seq(self) -> Result<Self::Items, Self> {
+        match self {
+            rmpv::Value::Array(arr) => Ok(arr.iter()),
+            _ => Err(self),
+        }
+    }
+
+    fn into_map(self) -> Result<Self::Entries, Self> {
+        match self {
+            rmpv::Value::Map(map) => Ok(map.iter().map(|(k, v)| (k, v))),
+            _ => Err(self),
+        }
+    }
+
+    fn into_ext(self) -> Result<(i8, Self::Bytes), Self> {
+        match self {
+            rmpv::Value::Ext(tag, data) => Ok((*tag, data)),
+            _ => Err(self),
+        }
+    }
+}
+
+impl<'de> Input<'de> for rmpv::Value {
+    type Bytes = Vec<u8>;
+    type Items = std::vec::IntoIter<rmpv::Value>;
+    type Entries = std::vec::IntoIter<(rmpv::Value, rmpv::Value)>;
+
+    fn value(&self) -> &rmpv::Value {
+        self
+    }
+
+    fn visit_str<V: Visitor<'de>>(self, visitor: V) -> RResult<V::Value> {
+        match self {
+            rmpv::Value::String(s) => match String::from_utf8(s.into_bytes()) {
+                Ok(s) => visitor.visit_string(s),
+                Err(e) => e.into_bytes().visit(visitor),
+            },
+            rmpv::Value::Binary(b) => b.visit(visitor),
+            _ => Err(Error::type_error("string", self.value())),
+        }
+    }
+
+    fn visit_bytes<V: Visitor<'de>>(self, visitor: V) -> RResult<V::Value> {
+        match self {
+            rmpv::Value::String(s) => s.into_bytes().visit(visitor),
+            rmpv::Value::Binary(b) => b.visit(visitor),
+            other => Err(Error::type_error("binary", &other)),
+        }
+    }
+
+    fn into_seq(self) -> Result<Self::Items, Self> {
+        match self {
+            rmpv::Value::Array(arr) => Ok(arr.into_iter()),
+            _ => Err(self),
+        }
+    }
+
+    fn into_map(self) -> Result<Self::Entries, Self> {
+        match self {
+            rmpv::Value::Map(map) => Ok(map.into_iter()),
+            _ => Err(self),
+        }
+    }
+
+    fn into_ext(self) -> Result<(i8, Self::Bytes), Self> {
+        match self {
+            rmpv::Value::Ext(tag, data) => Ok((tag, data)),
+            _ => Err(self),
+        }
+    }
+}
+
+/// A `serde::Deserializer` reading from an `rmpv::Value`.
+///
+/// `Deserializer<&rmpv::Value>` borrows from its input, while
+/// `Deserializer<rmpv::Value>` moves data out of it. Both are also available
+/// through `IntoDeserializer`. The deserializers for nested values borrow
+/// the configuration of their parent.
+///
+/// # Example
+/// ```rust
+/// use serde::Deserialize;
+/// use serde_rmpv::Deserializer;
+///
+/// let value = rmpv::Value::from("hello");
+/// let s = <&str>::deserialize(Deserializer::new(&value)).unwrap();
+/// assert_eq!("hello", s);
+/// ```
+pub struct Deserializer<'c, I> {
+    input: I,
+    config: Cow<'c, Config>,
+}
+
+impl<'de> Deserializer<'static, &'de rmpv::Value> {
+    /// Creates a deserializer borrowing from `input`.
+    pub fn new(input: &'de rmpv::Value) -> Self {
+        Deserializer::from_input(input, Cow::Owned(Config::default()))
+    }
+}
+
+impl Deserializer<'static, rmpv::Value> {
+    /// Creates a deserializer that takes ownership of `input`.
+    pub fn from_owned(input: rmpv::Value) -> Self {
+        Deserializer::from_input(input, Cow::Owned(Config::default()))
+    }
+}
+
+impl<'c, I> Deserializer<'c, I> {
+    /// Sets the configuration used while deserializing.
+    pub fn with_config(mut self, config: Config) -> Self {
+        self.config = Cow::Owned(config);
+        self
+    }
+
+    fn from_input(input: I, config: Cow<'c, Config>) -> Self {
+        Deserializer { input, config }
+    }
 
-struct Deserializer<'de> {
-    input: &'de rmpv::Value,
+    // A deserializer for a value nested in the input, sharing `config`.
+    fn nested(input: I, config: &'c Config) -> Self {
+        Deserializer::from_input(input, Cow::Borrowed(config))
+    }
+}
+
+// Narrow `value` to the integer type `T`, checked or wrapping according to the
+// configured `IntegerPolicy`.
+fn integer<T: Narrow>(value: &rmpv::Value, config: &Config) -> RResult<T> {
+    let v = match value {
+        rmpv::Value::Integer(i) => i
+            .as_i64()
+            .map(i128::from)
+            .or_else(|| i.as_u64().map(i128::from)),
+        _ => None,
+    }
+    .ok_or_else(|| Error::type_error(T::NAME, value))?;
+    match config.integer_policy {
+        IntegerPolicy::Checked => T::try_from(v).map_err(|_| {
+            ErrorKind::IntegerOutOfRange {
+                value: v,
+                target: T::NAME,
+            }
+            .into()
+        }),
+        IntegerPolicy::Wrapping => Ok(T::wrapping(v)),
+    }
 }
 
-impl<'de> Deserializer<'de> {
-    pub fn from_value(input: &'de rmpv::Value) -> Self {
-        Deserializer { input }
+// The big-endian bytes of a 128-bit integer written with an `Int128Fallback`,
+// if `value` is one.
+fn int128_bytes(value: &rmpv::Value, config: &Config) -> Option<[u8; 16]> {
+    match value {
+        rmpv::Value::Binary(b) => b.as_slice().try_into().ok(),
+        rmpv::Value::Ext(tag, b) if config.int128_fallback == Int128Fallback::Ext(*tag) => {
+            b.as_slice().try_into().ok()
+        }
+        _ => None,
     }
 }
 
+// Integer types that rmpv integers can be narrowed into.
+trait Narrow: TryFrom<i128> {
+    const NAME: &'static str;
+
+    fn wrapping(v: i128) -> Self;
+}
+
+macro_rules! impl_narrow {
+    ($($t:ty),*) => {
+        $(
+            impl Narrow for $t {
+                const NAME: &'static str = stringify!($t);
+
+                fn wrapping(v: i128) -> Self {
+                    v as $t
+                }
+            }
+        )*
+    };
+}
+
+impl_narrow!(i8, i16, i32, i64, i128, u8, u16, u32, u64, u128);
+
 pub fn from_value<'a, T>(s: &'a rmpv::Value) -> Result<T, Error>
 where
     T: Deserialize<'a>,
 {
-    let mut deserializer = Deserializer::from_value(s);
-    T::deserialize(&mut deserializer)
+    from_value_with(s, &Config::default())
+}
+
+pub fn from_value_with<'a, T>(s: &'a rmpv::Value, config: &Config) -> Result<T, Error>
+where
+    T: Deserialize<'a>,
+{
+    T::deserialize(Deserializer::nested(s, config))
 }
 
-impl<'de> de::Deserializer<'de> for &mut Deserializer<'de> {
+pub fn from_value_owned<T>(s: rmpv::Value) -> Result<T, Error>
+where
+    T: DeserializeOwned,
+{
+    from_value_owned_with(s, &Config::default())
+}
+
+pub fn from_value_owned_with<T>(s: rmpv::Value, config: &Config) -> Result<T, Error>
+where
+    T: DeserializeOwned,
+{
+    T::deserialize(Deserializer::nested(s, config))
+}
+
+impl<'de> de::IntoDeserializer<'de, Error> for &'de rmpv::Value {
+    type Deserializer = Deserializer<'static, &'de rmpv::Value>;
+
+    fn into_deserializer(self) -> Self::Deserializer {
+        Deserializer::new(self)
+    }
+}
+
+impl<'de> de::IntoDeserializer<'de, Error> for rmpv::Value {
+    type Deserializer = Deserializer<'static, rmpv::Value>;
+
+    fn into_deserializer(self) -> Self::Deserializer {
+        Deserializer::from_owned(self)
+    }
+}
+
+// Deserialize a value with a tag from the configured `ExtRegistry` through its
+// decoded form, by calling `$method` again on that instead. Only the typed
+// methods decode: `deserialize_any` hands the Ext value over as it is, so that
+// `rmpv::Value` targets, untagged enums and `IgnoredAny` see the input itself.
+macro_rules! decode_ext {
+    ($self:ident.$method:ident($($arg:ident),*)) => {
+        if let rmpv::Value::Ext(tag, data) = $self.input.value() {
+            if let Some(entry) = $self.config.ext_registry.by_tag(*tag) {
+                let config = $self.config.without_ext_registry();
+                let value = entry.decode(data, &config)?;
+                return de::Deserializer::$method(Deserializer::from_input(value, Cow::Owned(config)), $($arg),*);
+            }
+        }
+    };
+}
+
+impl<'de, I: Input<'de>> de::Deserializer<'de> for Deserializer<'_, I> {
     type Error = Error;
 
     fn deserialize_any<V>(self, visitor: V) -> RResult<V::Value>
     where
         V: Visitor<'de>,
     {
-        match self.input {
+        match self.input.value() {
             rmpv::Value::Nil => self.deserialize_unit(visitor),
             rmpv::Value::Boolean(_) => self.deserialize_bool(visitor),
-            rmpv::Value::Integer(_) => self.deserialize_i64(visitor),
+            rmpv::Value::Integer(i) => match i.as_u64() {
+                Some(v) => visitor.visit_u64(v),
+                None => self.deserialize_i64(visitor),
+            },
+            rmpv::Value::F32(v) => visitor.visit_f32(*v),
+            rmpv::Value::F64(v) => visitor.visit_f64(*v),
             rmpv::Value::String(_) => self.deserialize_string(visitor),
             rmpv::Value::Array(_) => self.deserialize_seq(visitor),
             rmpv::Value::Map(_) => self.deserialize_map(visitor),
             rmpv::Value::Binary(_) => self.deserialize_bytes(visitor),
-            _ => Err(Error::UnsupportedType),
+            // Ext is surfaced as the `_ExtStruct((tag, binary))` newtype.
+            rmpv::Value::Ext(_, _) => match self.input.into_ext() {
+                Ok((tag, data)) => visitor.visit_newtype_struct(ExtDeserializer::new(tag, data)),
+                Err(input) => Err(Error::type_error("ext", input.value())),
+            },
         }
     }
 
@@ -46,116 +361,112 @@ impl<'de> de::Deserializer<'de> for &mut Deserializer<'de> {
     where
         V: Visitor<'de>,
     {
+        decode_ext!(self.deserialize_bool(visitor));
         visitor.visit_bool(
             self.input
+                .value()
                 .as_bool()
-                .ok_or(Error::TypeError("expected bool".to_string()))?,
+                .ok_or_else(|| Error::type_error("bool", self.input.value()))?,
         )
     }
 
-    // The `parse_signed` function is generic over the integer type `T` so here
-    // it is invoked with `T=i8`. The next 8 methods are similar.
+    // The `integer` function is generic over the integer type `T` so here it
+    // is invoked with `T=i8`. The next 8 methods are similar.
     fn deserialize_i8<V>(self, visitor: V) -> RResult<V::Value>
     where
         V: Visitor<'de>,
     {
-        visitor.visit_i8(
-            self.input
-                .as_i64()
-                .map(|v| v as i8)
-                .ok_or(Error::TypeError("expected i8".to_string()))?,
-        )
+        decode_ext!(self.deserialize_i8(visitor));
+        visitor.visit_i8(integer(self.input.value(), &self.config)?)
     }
 
     fn deserialize_i16<V>(self, visitor: V) -> RResult<V::Value>
     where
         V: Visitor<'de>,
     {
-        visitor.visit_i16(
-            self.input
-                .as_i64()
-                .map(|v| v as i16)
-                .ok_or(Error::TypeError("expected i16".to_string()))?,
-        )
+        decode_ext!(self.deserialize_i16(visitor));
+        visitor.visit_i16(integer(self.input.value(), &self.config)?)
     }
 
     fn deserialize_i32<V>(self, visitor: V) -> RResult<V::Value>
     where
         V: Visitor<'de>,
     {
-        visitor.visit_i32(
-            self.input
-                .as_i64()
-                .ok_or(Error::TypeError("expected i32".to_string()))? as i32,
-        )
+        decode_ext!(self.deserialize_i32(visitor));
+        visitor.visit_i32(integer(self.input.value(), &self.config)?)
     }
 
     fn deserialize_i64<V>(self, visitor: V) -> RResult<V::Value>
     where
         V: Visitor<'de>,
     {
-        visitor.visit_i64(
-            self.input
-                .as_i64()
-                .ok_or(Error::TypeError("expected i64".to_string()))?,
-        )
+        decode_ext!(self.deserialize_i64(visitor));
+        visitor.visit_i64(integer(self.input.value(), &self.config)?)
+    }
+
+    fn deserialize_i128<V>(self, visitor: V) -> RResult<V::Value>
+    where
+        V: Visitor<'de>,
+    {
+        decode_ext!(self.deserialize_i128(visitor));
+        match int128_bytes(self.input.value(), &self.config) {
+            Some(bytes) => visitor.visit_i128(i128::from_be_bytes(bytes)),
+            None => visitor.visit_i128(integer(self.input.value(), &self.config)?),
+        }
     }
 
     fn deserialize_u8<V>(self, visitor: V) -> RResult<V::Value>
     where
         V: Visitor<'de>,
     {
-        visitor.visit_u8(
-            self.input
-                .as_u64()
-                .map(|v| v as u8)
-                .ok_or(Error::TypeError("expected u8".to_string()))?,
-        )
+        decode_ext!(self.deserialize_u8(visitor));
+        visitor.visit_u8(integer(self.input.value(), &self.config)?)
     }
 
     fn deserialize_u16<V>(self, visitor: V) -> RResult<V::Value>
     where
         V: Visitor<'de>,
     {
-        visitor.visit_u16(
-            self.input
-                .as_u64()
-                .map(|v| v as u16)
-                .ok_or(Error::TypeError("expected u16".to_string()))?,
-        )
+        decode_ext!(self.deserialize_u16(visitor));
+        visitor.visit_u16(integer(self.input.value(), &self.config)?)
     }
 
     fn deserialize_u32<V>(self, visitor: V) -> RResult<V::Value>
     where
         V: Visitor<'de>,
     {
-        visitor.visit_u32(
-            self.input
-                .as_u64()
-                .map(|v| v as u32)
-                .ok_or(Error::TypeError("expected u32".to_string()))?,
-        )
+        decode_ext!(self.deserialize_u32(visitor));
+        visitor.visit_u32(integer(self.input.value(), &self.config)?)
     }
 
     fn deserialize_u64<V>(self, visitor: V) -> RResult<V::Value>
     where
         V: Visitor<'de>,
     {
-        visitor.visit_u64(
-            self.input
-                .as_u64()
-                .ok_or(Error::TypeError("expected u64".to_string()))?,
-        )
+        decode_ext!(self.deserialize_u64(visitor));
+        visitor.visit_u64(integer(self.input.value(), &self.config)?)
+    }
+
+    fn deserialize_u128<V>(self, visitor: V) -> RResult<V::Value>
+    where
+        V: Visitor<'de>,
+    {
+        decode_ext!(self.deserialize_u128(visitor));
+        match int128_bytes(self.input.value(), &self.config) {
+            Some(bytes) => visitor.visit_u128(u128::from_be_bytes(bytes)),
+            None => visitor.visit_u128(integer(self.input.value(), &self.config)?),
+        }
     }
 
     fn deserialize_f32<V>(self, visitor: V) -> RResult<V::Value>
     where
         V: Visitor<'de>,
     {
-        match self.input {
+        decode_ext!(self.deserialize_f32(visitor));
+        match self.input.value() {
             rmpv::Value::F32(v) => visitor.visit_f32(*v),
             rmpv::Value::F64(v) => visitor.visit_f32(*v as f32),
-            _ => Err(Error::TypeError("expected f32".to_string())),
+            other => Err(Error::type_error("f32", other)),
         }
     }
 
@@ -163,10 +474,12 @@ impl<'de> de::Deserializer<'de> for &mut Deserializer<'de> {
     where
         V: Visitor<'de>,
     {
+        decode_ext!(self.deserialize_f64(visitor));
         visitor.visit_f64(
             self.input
+                .value()
                 .as_f64()
-                .ok_or(Error::TypeError("expected f64".to_string()))?,
+                .ok_or_else(|| Error::type_error("f64", self.input.value()))?,
         )
     }
 
@@ -174,18 +487,15 @@ impl<'de> de::Deserializer<'de> for &mut Deserializer<'de> {
     where
         V: Visitor<'de>,
     {
-        match self.input {
-            rmpv::Value::String(s) => {
-                let s = s
-                    .as_str()
-                    .ok_or(Error::TypeError("expected string".to_string()))?;
-                let mut chars = s.chars();
-                match (chars.next(), chars.next()) {
-                    (Some(c), None) => visitor.visit_char(c),
-                    _ => Err(Error::TypeError("expected single char".to_string())),
-                }
-            }
-            _ => Err(Error::TypeError("expected string".to_string())),
+        decode_ext!(self.deserialize_char(visitor));
+        let value = self.input.value();
+        let mut chars = value
+            .as_str()
+            .ok_or_else(|| Error::type_error("char", value))?
+            .chars();
+        match (chars.next(), chars.next()) {
+            (Some(c), None) => visitor.visit_char(c),
+            _ => Err(Error::type_error("single char", value)),
         }
     }
 
@@ -193,20 +503,15 @@ impl<'de> de::Deserializer<'de> for &mut Deserializer<'de> {
     where
         V: Visitor<'de>,
     {
-        match self.input {
-            rmpv::Value::String(s) => match s.as_str() {
-                Some(s) => visitor.visit_borrowed_str(s),
-                None => visitor.visit_bytes(s.as_bytes()),
-            },
-            rmpv::Value::Binary(b) => visitor.visit_bytes(b),
-            _ => Err(Error::TypeError(format!("expected string: {}", self.input))),
-        }
+        decode_ext!(self.deserialize_str(visitor));
+        self.input.visit_str(visitor)
     }
 
     fn deserialize_string<V>(self, visitor: V) -> RResult<V::Value>
     where
         V: Visitor<'de>,
     {
+        decode_ext!(self.deserialize_string(visitor));
         self.deserialize_str(visitor)
     }
 
@@ -214,29 +519,23 @@ impl<'de> de::Deserializer<'de> for &mut Deserializer<'de> {
     where
         V: Visitor<'de>,
     {
-        visitor.visit_bytes(
-            self.input
-                .as_slice()
-                .ok_or(Error::TypeError("expected binary".to_string()))?,
-        )
+        decode_ext!(self.deserialize_bytes(visitor));
+        self.input.visit_bytes(visitor)
     }
 
     fn deserialize_byte_buf<V>(self, visitor: V) -> RResult<V::Value>
     where
         V: Visitor<'de>,
     {
-        visitor.visit_bytes(
-            self.input
-                .as_slice()
-                .ok_or(Error::TypeError("expected binary".to_string()))?,
-        )
+        decode_ext!(self.deserialize_byte_buf(visitor));
+        self.deserialize_bytes(visitor)
     }
 
     fn deserialize_option<V>(self, visitor: V) -> RResult<V::Value>
     where
         V: Visitor<'de>,
     {
-        match self.input {
+        match self.input.value() {
             rmpv::Value::Nil => visitor.visit_none(),
             _ => visitor.visit_some(self),
         }
@@ -247,40 +546,110 @@ impl<'de> de::Deserializer<'de> for &mut Deserializer<'de> {
     where
         V: Visitor<'de>,
     {
-        match self.input {
+        decode_ext!(self.deserialize_unit(visitor));
+        match self.input.value() {
             rmpv::Value::Nil => visitor.visit_unit(),
-            _ => Err(Error::TypeError("expected nil".to_string())),
+            other => Err(Error::type_error("nil", other)),
         }
     }
 
     fn deserialize_enum<V>(
         self,
-        _name: &'static str,
-        _variants: &'static [&'static str],
+        name: &'static str,
+        variants: &'static [&'static str],
         visitor: V,
     ) -> RResult<V::Value>
     where
         V: Visitor<'de>,
     {
-        // FIXME: We only support unit variants for now
-        visitor.visit_enum(UnitVariantAccess::new(self))
+        decode_ext!(self.deserialize_enum(name, variants, visitor));
+        // We accept every `EnumRepr`, telling them apart by shape:
+        //   - unit variants: a bare variant name or index
+        //   - named array: `[ENUM_NAME, VARIANT_NAME, ... DATA ...]`
+        //   - index array: `[INDEX, ... DATA ...]`
+        //   - externally tagged: `{VARIANT_NAME_OR_INDEX: DATA}`
+        let Deserializer { input, config } = self;
+        let access = match input.value() {
+            rmpv::Value::String(_) | rmpv::Value::Integer(_) => {
+                VariantAccess::new(input, VariantData::Unit, &config)
+            }
+            rmpv::Value::Array(arr) => {
+                let named = match arr.as_slice() {
+                    [rmpv::Value::String(_), _, ..] => true,
+                    [rmpv::Value::Integer(_), ..] => false,
+                    _ => return Err(Error::type_error("enum", input.value())),
+                };
+                let mut items = input
+                    .into_seq()
+                    .map_err(|input| Error::type_error("enum", input.value()))?;
+                if named {
+                    let enum_name = items.next().map(|n| Unexpected::from(n.value()));
+                    match enum_name {
+                        Some(Unexpected::Str(n)) if n == name => {}
+                        Some(unexpected) if config.strict => {
+                            return Err(ErrorKind::InvalidValue {
+                                unexpected,
+                                expected: format!("enum {}", name),
+                            }
+                            .into());
+                        }
+                        _ => {}
+                    }
+                }
+                // The variant's fields follow the enum and variant names, or
+                // the index.
+                let first = if named { 2 } else { 1 };
+                match items.next() {
+                    Some(variant) => {
+                        VariantAccess::new(variant, VariantData::Fields(items, first), &config)
+                    }
+                    None => return Err(de::Error::invalid_length(0, &"enum")),
+                }
+            }
+            rmpv::Value::Map(map) if map.len() == 1 => {
+                match input
+                    .into_map()
+                    .map_err(|input| Error::type_error("enum", input.value()))?
+                    .next()
+                {
+                    Some((variant, data)) => {
+                        let segment = Segment::Key(key_name(variant.value(), None));
+                        VariantAccess::new(variant, VariantData::Tagged(data, segment), &config)
+                    }
+                    None => return Err(de::Error::invalid_length(0, &"enum")),
+                }
+            }
+            other => return Err(Error::type_error("enum", other)),
+        };
+        visitor.visit_enum(access)
     }
 
     // Unit struct means a named value containing no data.
-    fn deserialize_unit_struct<V>(self, _name: &'static str, visitor: V) -> RResult<V::Value>
+    fn deserialize_unit_struct<V>(self, name: &'static str, visitor: V) -> RResult<V::Value>
     where
         V: Visitor<'de>,
     {
-        self.deserialize_unit(visitor)
+        decode_ext!(self.deserialize_unit_struct(name, visitor));
+        match self.input.value() {
+            // Unit structs written with `UnitStructRepr::EmptyArray`.
+            rmpv::Value::Array(items) if items.is_empty() => visitor.visit_unit(),
+            _ => self.deserialize_unit(visitor),
+        }
     }
 
     // As is done here, serializers are encouraged to treat newtype structs as
     // insignificant wrappers around the data they contain. That means not
     // parsing anything other than the contained value.
-    fn deserialize_newtype_struct<V>(self, _name: &'static str, visitor: V) -> RResult<V::Value>
+    fn deserialize_newtype_struct<V>(self, name: &'static str, visitor: V) -> RResult<V::Value>
     where
         V: Visitor<'de>,
     {
+        match name {
+            // A lazy value captures the input through `deserialize_any`, so
+            // registered Ext types are decoded only when it is deserialized.
+            MSGPACK_EXT_STRUCT_NAME | LAZY_VALUE_STRUCT_NAME => {}
+            _ => decode_ext!(self.deserialize_newtype_struct(name, visitor)),
+        }
         visitor.visit_newtype_struct(self)
     }
 
@@ -291,64 +660,88 @@ impl<'de> de::Deserializer<'de> for &mut Deserializer<'de> {
     where
         V: Visitor<'de>,
     {
-        match self.input {
-            rmpv::Value::Binary(v) => visitor.visit_bytes(v),
-            rmpv::Value::Ext(_, _) => serde::Deserializer::deserialize_any(
-                ExtDeserializer::new(self.input.clone()),
-                visitor,
-            ),
-            rmpv::Value::Array(_) => visitor.visit_seq(ArrayAccess::new(self)),
-            _ => Err(Error::TypeError("expected sequence type".to_string())),
-        }
+        decode_ext!(self.deserialize_seq(visitor));
+        sequence(self, None, visitor)
     }
 
-    fn deserialize_tuple<V>(self, _len: usize, visitor: V) -> RResult<V::Value>
+    fn deserialize_tuple<V>(self, len: usize, visitor: V) -> RResult<V::Value>
     where
         V: Visitor<'de>,
     {
-        self.deserialize_seq(visitor)
+        decode_ext!(self.deserialize_tuple(len, visitor));
+        sequence(self, Some(len), visitor)
     }
 
     fn deserialize_tuple_struct<V>(
         self,
-        _name: &'static str,
-        _len: usize,
+        name: &'static str,
+        len: usize,
         visitor: V,
     ) -> RResult<V::Value>
     where
         V: Visitor<'de>,
     {
-        self.deserialize_seq(visitor)
+        decode_ext!(self.deserialize_tuple_struct(name, len, visitor));
+        sequence(self, Some(len), visitor)
     }
 
     fn deserialize_map<V>(self, visitor: V) -> RResult<V::Value>
     where
         V: Visitor<'de>,
     {
-        if let rmpv::Value::Map(_) = self.input {
-            visitor.visit_map(ValueMapAccess::new(self))
-        } else {
-            Err(Error::TypeError("expected map".to_string()))
+        decode_ext!(self.deserialize_map(visitor));
+        let Deserializer { input, config } = self;
+        let skipped = duplicate_keys(input.value(), None, &config)?;
+        match input.into_map() {
+            Ok(entries) => {
+                visitor.visit_map(ValueMapAccess::new(entries, &config).with_skipped(skipped))
+            }
+            Err(input) => Err(Error::type_error("map", input.value())),
         }
     }
 
+    // Identifiers are names, or indices for enums written with an index-based
+    // `EnumRepr`.
     fn deserialize_identifier<V>(self, visitor: V) -> RResult<V::Value>
     where
         V: Visitor<'de>,
     {
-        self.deserialize_str(visitor)
+        decode_ext!(self.deserialize_identifier(visitor));
+        match self.input.value() {
+            // Integers that can't be an index, such as negative ones, name no
+            // field, and are ignored like unknown names unless strict.
+            rmpv::Value::Integer(n) if n.as_u64().is_none() && !self.config.strict => {
+                visitor.visit_u64(u64::MAX)
+            }
+            rmpv::Value::Integer(_) => self.deserialize_u64(visitor),
+            _ => self.deserialize_str(visitor),
+        }
     }
 
     fn deserialize_struct<V>(
         self,
-        _name: &'static str,
-        _fields: &'static [&'static str],
+        name: &'static str,
+        fields: &'static [&'static str],
         visitor: V,
     ) -> RResult<V::Value>
     where
         V: Visitor<'de>,
     {
-        self.deserialize_map(visitor)
+        decode_ext!(self.deserialize_struct(name, fields, visitor));
+        // Structs written with `StructRepr::Array`.
+        if let rmpv::Value::Array(_) = self.input.value() {
+            return self.deserialize_seq(visitor);
+        }
+        let Deserializer { input, config } = self;
+        let skipped = duplicate_keys(input.value(), Some(fields), &config)?;
+        match input.into_map() {
+            Ok(entries) => visitor.visit_map(
+                ValueMapAccess::new(entries, &config)
+                    .with_fields(fields)
+                    .with_skipped(skipped),
+            ),
+            Err(input) => Err(Error::type_error("map", input.value())),
+        }
     }
 
     fn deserialize_ignored_any<V>(self, visitor: V) -> RResult<V::Value>
@@ -357,42 +750,81 @@ impl<'de> de::Deserializer<'de> for &mut Deserializer<'de> {
     {
         self.deserialize_any(visitor)
     }
+
+    // Must agree with the serializer, see `Config::with_human_readable`.
+    fn is_human_readable(&self) -> bool {
+        self.config.human_readable
+    }
 }
 
-struct ExtValueDeserializer {
-    value: rmpv::Value,
+struct ExtValueDeserializer<B> {
+    data: B,
+}
+
+impl<B> ExtValueDeserializer<B> {
+    fn new(data: B) -> Self {
+        ExtValueDeserializer { data }
+    }
 }
 
-impl ExtValueDeserializer {
-    fn new(value: rmpv::Value) -> Self {
-        ExtValueDeserializer { value }
+impl<'de, B: InputBytes<'de>> ExtValueDeserializer<B> {
+    // Presents the data as a sequence of u8s, for targets such as `Vec<u8>`
+    // and `[u8; N]` that don't ask for bytes.
+    fn visit_seq<V: Visitor<'de>>(self, visitor: V) -> RResult<V::Value> {
+        let bytes = self.data.as_ref().iter().copied();
+        de::Deserializer::deserialize_any(de::value::SeqDeserializer::new(bytes), visitor)
     }
 }
 
-impl<'de> serde::Deserializer<'de> for ExtValueDeserializer {
+impl<'de, B: InputBytes<'de>> serde::Deserializer<'de> for ExtValueDeserializer<B> {
     type Error = Error;
 
     fn deserialize_any<V>(self, visitor: V) -> RResult<V::Value, Error>
     where
         V: Visitor<'de>,
     {
-        let ret = visitor.visit_bytes(self.value.as_slice().unwrap())?;
-        Ok(ret)
+        self.data.visit(visitor)
+    }
+
+    fn deserialize_seq<V>(self, visitor: V) -> RResult<V::Value, Error>
+    where
+        V: Visitor<'de>,
+    {
+        self.visit_seq(visitor)
+    }
+
+    fn deserialize_tuple<V>(self, _len: usize, visitor: V) -> RResult<V::Value, Error>
+    where
+        V: Visitor<'de>,
+    {
+        self.visit_seq(visitor)
+    }
+
+    fn deserialize_tuple_struct<V>(
+        self,
+        _name: &'static str,
+        _len: usize,
+        visitor: V,
+    ) -> RResult<V::Value, Error>
+    where
+        V: Visitor<'de>,
+    {
+        self.visit_seq(visitor)
     }
 
     forward_to_deserialize_any! {
         bool i8 i16 i32 i64 i128 u8 u16 u32 u64 u128 f32 f64 char str string
-        bytes byte_buf option unit unit_struct newtype_struct seq tuple
-        tuple_struct map struct enum identifier ignored_any
+        bytes byte_buf option unit unit_struct newtype_struct map struct enum
+        identifier ignored_any
     }
 }
 
 struct ExtIdDeserializer {
-    id: rmpv::Value,
+    id: i8,
 }
 
 impl ExtIdDeserializer {
-    fn new(id: rmpv::Value) -> Self {
+    fn new(id: i8) -> Self {
         ExtIdDeserializer { id }
     }
 }
@@ -404,8 +836,7 @@ impl<'de> serde::Deserializer<'de> for ExtIdDeserializer {
     where
         V: Visitor<'de>,
     {
-        let ret = visitor.visit_i8(self.id.as_i64().unwrap() as i8)?;
-        Ok(ret)
+        visitor.visit_i8(self.id)
     }
 
     forward_to_deserialize_any! {
@@ -415,32 +846,32 @@ impl<'de> serde::Deserializer<'de> for ExtIdDeserializer {
     }
 }
 
-struct ExtDeserializer {
-    id: rmpv::Value,
-    data: rmpv::Value,
+/// Presents an Ext value as the `(tag, binary)` sequence of the
+/// `_ExtStruct` convention.
+struct ExtDeserializer<B> {
+    id: i8,
+    data: Option<B>,
     offset: usize,
 }
 
-impl ExtDeserializer {
-    fn new(value: rmpv::Value) -> Self {
-        let (id, data) = value.as_ext().expect("expected ext");
+impl<B> ExtDeserializer<B> {
+    fn new(id: i8, data: B) -> Self {
         ExtDeserializer {
-            id: rmpv::Value::from(id),
-            data: rmpv::Value::from(data),
+            id,
+            data: Some(data),
             offset: 0,
         }
     }
 }
 
-impl<'de> serde::Deserializer<'de> for ExtDeserializer {
+impl<'de, B: InputBytes<'de>> serde::Deserializer<'de> for ExtDeserializer<B> {
     type Error = Error;
 
     fn deserialize_any<V>(mut self, visitor: V) -> RResult<V::Value, Error>
     where
         V: Visitor<'de>,
     {
-        let ret = visitor.visit_seq(&mut self)?;
-        Ok(ret)
+        visitor.visit_seq(&mut self)
     }
 
     forward_to_deserialize_any! {
@@ -450,7 +881,7 @@ impl<'de> serde::Deserializer<'de> for ExtDeserializer {
     }
 }
 
-impl<'de> SeqAccess<'de> for ExtDeserializer {
+impl<'de, B: InputBytes<'de>> SeqAccess<'de> for ExtDeserializer<B> {
     type Error = Error;
 
     fn next_element_seed<T>(&mut self, seed: T) -> RResult<Option<T::Value>, Error>
@@ -460,126 +891,284 @@ impl<'de> SeqAccess<'de> for ExtDeserializer {
         match self.offset {
             0 => {
                 self.offset += 1;
-                let de = ExtIdDeserializer::new(self.id.clone());
+                let de = ExtIdDeserializer::new(self.id);
                 let v = seed.deserialize(de)?;
                 Ok(Some(v))
             }
             1 => {
                 self.offset += 1;
-                let de = ExtValueDeserializer::new(self.data.clone());
-                let v = seed.deserialize(de)?;
-                Ok(Some(v))
+                match self.data.take() {
+                    Some(data) => Ok(Some(seed.deserialize(ExtValueDeserializer::new(data))?)),
+                    None => Ok(None),
+                }
             }
             _ => Ok(None),
         }
     }
+
+    fn size_hint(&self) -> Option<usize> {
+        Some(2 - self.offset.min(2))
+    }
 }
 
-// struct ExtAccess<'a, 'de: 'a> {
-//     de: &'a mut Deserializer<'de>,
-//     offset: usize,
-// }
-//
-// impl<'a, 'de> ExtAccess<'a, 'de> {
-//     fn new(de: &'a mut Deserializer<'de>) -> Self {
-//         ExtAccess { de, offset: 0 }
-//     }
-// }
-//
-// impl<'de, 'a> SeqAccess<'de> for ExtAccess<'a, 'de> {
-//     type Error = Error;
-//
-//     fn next_element_seed<T>(&mut self, seed: T) -> Result<Option<T::Value>>
-//     where
-//         T: DeserializeSeed<'de>,
-//     {
-//         let (id, val) = self.de.input.as_ext().expect("expected ext");
-//         match self.offset {
-//             0 => {
-//                 self.offset += 1;
-//                 let value = rmpv::Value::from(id);
-//                 let mut de = Deserializer::from_value(&value);
-//                 seed.deserialize(&mut de).map(Some)
-//             }
-//             1 => {
-//                 self.offset += 1;
-//                 let value = rmpv::Value::Binary(val.to_vec());
-//                 let mut de = Deserializer::from_value(&value);
-//                 seed.deserialize(&mut de).map(Some)
-//             }
-//             _ => Ok(None),
-//         }
-//     }
-// }
-
-struct ArrayAccess<'a, 'de: 'a> {
-    de: &'a mut Deserializer<'de>,
-    offset: usize,
+// Deserialize a sequence of `len` elements, if known, from an array, or from
+// Ext or Binary data.
+fn sequence<'de, I, V>(de: Deserializer<'_, I>, len: Option<usize>, visitor: V) -> RResult<V::Value>
+where
+    I: Input<'de>,
+    V: Visitor<'de>,
+{
+    let Deserializer { input, config } = de;
+    match input.value() {
+        rmpv::Value::Binary(_) => input.visit_bytes(visitor),
+        rmpv::Value::Ext(_, _) | rmpv::Value::Array(_) => {
+            let input = match input.into_ext() {
+                Ok((tag, data)) => {
+                    return serde::Deserializer::deserialize_any(
+                        ExtDeserializer::new(tag, data),
+                        visitor,
+                    )
+                }
+                Err(input) => input,
+            };
+            match input.into_seq() {
+                Ok(items) => visit_array(items, len, 0, &config, visitor),
+                Err(input) => Err(Error::type_error("sequence", input.value())),
+            }
+        }
+        other => Err(Error::type_error("sequence", other)),
+    }
+}
+
+// Visit the items of an array, the first of which is at index `first` of the
+// enclosing array in error paths. In strict mode, the array must have `len`
+// items, if known, and the visitor must read all of them.
+fn visit_array<'de, S, I, V>(
+    items: S,
+    len: Option<usize>,
+    first: usize,
+    config: &Config,
+    visitor: V,
+) -> RResult<V::Value>
+where
+    S: ExactSizeIterator<Item = I>,
+    I: Input<'de>,
+    V: Visitor<'de>,
+{
+    let total = items.len();
+    if let (true, Some(len)) = (config.strict, len) {
+        if len != total {
+            let expected = format!("{} elements", len);
+            return Err(de::Error::invalid_length(total, &expected.as_str()));
+        }
+    }
+    let mut access = ArrayAccess::new(items, first, config);
+    let value = visitor.visit_seq(&mut access)?;
+    if config.strict && access.items.len() > 0 {
+        let expected = format!("{} elements", access.index - first);
+        return Err(de::Error::invalid_length(total, &expected.as_str()));
+    }
+    Ok(value)
+}
+
+struct ArrayAccess<'a, S> {
+    items: S,
+    // The index of the next element, for error paths.
+    index: usize,
+    config: &'a Config,
 }
 
-impl<'a, 'de> ArrayAccess<'a, 'de> {
-    fn new(de: &'a mut Deserializer<'de>) -> Self {
-        ArrayAccess { de, offset: 0 }
+impl<'a, S> ArrayAccess<'a, S> {
+    fn new(items: S, index: usize, config: &'a Config) -> Self {
+        ArrayAccess {
+            items,
+            index,
+            config,
+        }
     }
 }
 
-impl<'de> SeqAccess<'de> for ArrayAccess<'_, 'de> {
+impl<'de, S, I> SeqAccess<'de> for ArrayAccess<'_, S>
+where
+    S: ExactSizeIterator<Item = I>,
+    I: Input<'de>,
+{
     type Error = Error;
 
     fn next_element_seed<T>(&mut self, seed: T) -> RResult<Option<T::Value>>
     where
         T: DeserializeSeed<'de>,
     {
-        let arr = self
-            .de
-            .input
-            .as_array()
-            .ok_or(Error::TypeError("expected array".to_string()))?;
-        if self.offset < arr.len() {
-            let mut d = Deserializer::from_value(&arr[self.offset]);
-            self.offset += 1;
-            Ok(Some(
-                seed.deserialize(&mut d)
-                    .map_err(|e| Error::Format(e.to_string()))?,
-            ))
-        } else {
-            Ok(None)
+        match self.items.next() {
+            Some(item) => {
+                let d = Deserializer::nested(item, self.config);
+                let index = self.index;
+                self.index += 1;
+                Ok(Some(
+                    seed.deserialize(d)
+                        .map_err(|e| e.at(Segment::Index(index)))?,
+                ))
+            }
+            None => Ok(None),
         }
     }
+
+    fn size_hint(&self) -> Option<usize> {
+        Some(self.items.len())
+    }
 }
 
-struct ValueMapAccess<'a, 'de: 'a> {
-    de: &'a mut Deserializer<'de>,
-    offset: usize,
+// The struct field that an integer key refers to by index, as written with
+// `StructRepr::FieldIndex`.
+fn indexed_field(
+    key: &rmpv::Value,
+    fields: Option<&'static [&'static str]>,
+) -> Option<&'static str> {
+    let index = usize::try_from(key.as_u64()?).ok()?;
+    fields?.get(index).copied()
+}
+
+// The struct field that a map key refers to: by name, as a string or binary,
+// or by index.
+fn struct_field(
+    key: &rmpv::Value,
+    fields: Option<&'static [&'static str]>,
+) -> Option<&'static str> {
+    let name = match key {
+        rmpv::Value::String(s) => s.as_bytes(),
+        rmpv::Value::Binary(b) => b.as_slice(),
+        _ => return indexed_field(key, fields),
+    };
+    fields?.iter().find(|f| f.as_bytes() == name).copied()
+}
+
+// The name of a map key in error paths.
+fn key_name(key: &rmpv::Value, field: Option<&str>) -> String {
+    match (field, key) {
+        (Some(field), _) => field.to_string(),
+        (None, rmpv::Value::String(s)) => String::from_utf8_lossy(s.as_bytes()).into(),
+        (None, other) => other.to_string(),
+    }
 }
 
-impl<'a, 'de> ValueMapAccess<'a, 'de> {
-    fn new(de: &'a mut Deserializer<'de>) -> Self {
-        ValueMapAccess { de, offset: 0 }
+// Which entries of a map to skip under the configured `DuplicateKeyPolicy`,
+// or an error for a duplicate key. Keys are compared by their encoding, except
+// that keys referring to the same struct field are the same.
+fn duplicate_keys(
+    map: &rmpv::Value,
+    fields: Option<&'static [&'static str]>,
+    config: &Config,
+) -> RResult<Vec<bool>> {
+    let entries = match (config.duplicate_key_policy, map) {
+        (DuplicateKeyPolicy::Allow, _) => return Ok(Vec::new()),
+        (_, rmpv::Value::Map(entries)) => entries,
+        _ => return Ok(Vec::new()),
+    };
+    let mut skipped = vec![false; entries.len()];
+    let mut seen = HashMap::with_capacity(entries.len());
+    for (i, (key, _)) in entries.iter().enumerate() {
+        let field = struct_field(key, fields);
+        let mut encoded = Vec::new();
+        let written = match field {
+            Some(field) => rmpv::encode::write_value(&mut encoded, &rmpv::Value::from(field)),
+            None => rmpv::encode::write_value(&mut encoded, key),
+        };
+        written.map_err(<Error as de::Error>::custom)?;
+        match seen.entry(encoded) {
+            Entry::Vacant(entry) => {
+                entry.insert(i);
+            }
+            Entry::Occupied(mut entry) => match config.duplicate_key_policy {
+                DuplicateKeyPolicy::Error => {
+                    let name = key_name(key, field);
+                    return Err(
+                        Error::from(ErrorKind::DuplicateKey(name.clone())).at(Segment::Key(name))
+                    );
+                }
+                DuplicateKeyPolicy::FirstWins => skipped[i] = true,
+                DuplicateKeyPolicy::LastWins | DuplicateKeyPolicy::Allow => {
+                    skipped[entry.insert(i)] = true;
+                }
+            },
+        }
     }
+    Ok(skipped)
 }
 
-impl<'de> MapAccess<'de> for ValueMapAccess<'_, 'de> {
+struct ValueMapAccess<'a, M, I> {
+    entries: M,
+    // Entries to skip, by position, under the `DuplicateKeyPolicy`.
+    skipped: Vec<bool>,
+    position: usize,
+    // The key and value of the entry whose key was just read.
+    value: Option<(Segment, I)>,
+    // The fields of the struct being deserialized, if any, to which integer
+    // keys refer by index.
+    fields: Option<&'static [&'static str]>,
+    config: &'a Config,
+}
+
+impl<'a, M, I> ValueMapAccess<'a, M, I> {
+    fn new(entries: M, config: &'a Config) -> Self {
+        ValueMapAccess {
+            entries,
+            skipped: Vec::new(),
+            position: 0,
+            value: None,
+            fields: None,
+            config,
+        }
+    }
+
+    fn with_skipped(mut self, skipped: Vec<bool>) -> Self {
+        self.skipped = skipped;
+        self
+    }
+
+    fn with_fields(mut self, fields: &'static [&'static str]) -> Self {
+        self.fields = Some(fields);
+        self
+    }
+}
+
+impl<'de, M, I> MapAccess<'de> for ValueMapAccess<'_, M, I>
+where
+    M: ExactSizeIterator<Item = (I, I)>,
+    I: Input<'de>,
+{
     type Error = Error;
 
     fn next_key_seed<K>(&mut self, seed: K) -> RResult<Option<K::Value>>
     where
         K: DeserializeSeed<'de>,
     {
-        let m = self
-            .de
-            .input
-            .as_map()
-            .ok_or(Error::TypeError("expected map".to_string()))?;
-        if self.offset < m.len() {
-            let mut d = Deserializer::from_value(&m[self.offset].0);
-            self.offset += 1;
-            Ok(Some(
-                seed.deserialize(&mut d)
-                    .map_err(|e| Error::Format(e.to_string()))?,
-            ))
-        } else {
-            Ok(None)
+        let mut next = self.entries.next();
+        while next.is_some() && self.skipped.get(self.position) == Some(&true) {
+            self.position += 1;
+            next = self.entries.next();
+        }
+        self.position += 1;
+        match next {
+            Some((key, value)) => {
+                // Keys written with `StructRepr::FieldIndex` stand for the
+                // field name.
+                let field = indexed_field(key.value(), self.fields);
+                let segment = Segment::Key(key_name(key.value(), field));
+                if self.config.strict
+                    && self.fields.is_some()
+                    && field.is_none()
+                    && !key.value().is_str()
+                {
+                    return Err(Error::type_error("a field name", key.value()).at(segment));
+                }
+                self.value = Some((segment.clone(), value));
+                let key = match field {
+                    Some(field) => seed.deserialize(de::value::BorrowedStrDeserializer::new(field)),
+                    None => seed.deserialize(Deserializer::nested(key, self.config)),
+                };
+                Ok(Some(key.map_err(|e| e.at(segment))?))
+            }
+            None => Ok(None),
         }
     }
 
@@ -587,79 +1176,161 @@ impl<'de> MapAccess<'de> for ValueMapAccess<'_, 'de> {
     where
         V: DeserializeSeed<'de>,
     {
-        let m = self
-            .de
-            .input
-            .as_map()
-            .ok_or(Error::TypeError("expected map".to_string()))?;
-        let mut d = Deserializer::from_value(&m[self.offset - 1].1);
-        seed.deserialize(&mut d)
-            .map_err(|e| Error::Format(e.to_string()))
+        let (segment, value) = self
+            .value
+            .take()
+            .ok_or_else(|| <Error as de::Error>::custom("value is missing"))?;
+        let d = Deserializer::nested(value, self.config);
+        seed.deserialize(d).map_err(|e| e.at(segment))
+    }
+
+    fn size_hint(&self) -> Option<usize> {
+        let skipped = self
+            .skipped
+            .iter()
+            .skip(self.position)
+            .filter(|s| **s)
+            .count();
+        Some(self.entries.len() - skipped)
     }
 }
 
-struct UnitVariantAccess<'a, 'de: 'a> {
-    de: &'a mut Deserializer<'de>,
+/// The data that accompanies an enum variant.
+enum VariantData<I, S> {
+    /// A bare unit variant.
+    Unit,
+    /// A single value wrapping the variant's fields, as in `{VARIANT: DATA}`,
+    /// with the variant key for error paths.
+    Tagged(I, Segment),
+    /// The variant's fields flattened into the remainder of an array, with the
+    /// index of the first field in that array.
+    Fields(S, usize),
 }
 
-impl<'a, 'de> UnitVariantAccess<'a, 'de> {
-    fn new(de: &'a mut Deserializer<'de>) -> Self {
-        UnitVariantAccess { de }
+/// Gives access to an enum variant: the variant identifier, plus the data that
+/// accompanies it.
+struct VariantAccess<'a, I, S> {
+    variant: I,
+    data: VariantData<I, S>,
+    config: &'a Config,
+}
+
+impl<'a, I, S> VariantAccess<'a, I, S> {
+    fn new(variant: I, data: VariantData<I, S>, config: &'a Config) -> Self {
+        VariantAccess {
+            variant,
+            data,
+            config,
+        }
     }
 }
 
-impl<'de> de::EnumAccess<'de> for UnitVariantAccess<'_, 'de> {
+impl<'a, 'de, I, S> de::EnumAccess<'de> for VariantAccess<'a, I, S>
+where
+    I: Input<'de>,
+    S: ExactSizeIterator<Item = I>,
+{
     type Error = Error;
-    type Variant = Self;
+    type Variant = VariantDataAccess<'a, I, S>;
 
-    fn variant_seed<V>(self, seed: V) -> RResult<(V::Value, Self), Error>
+    fn variant_seed<V>(self, seed: V) -> RResult<(V::Value, Self::Variant), Error>
     where
         V: DeserializeSeed<'de>,
     {
-        let variant = seed.deserialize(&mut *self.de)?;
-        Ok((variant, self))
+        let d = Deserializer::nested(self.variant, self.config);
+        let variant = seed.deserialize(d)?;
+        Ok((
+            variant,
+            VariantDataAccess {
+                data: self.data,
+                config: self.config,
+            },
+        ))
     }
 }
 
-impl<'de> de::VariantAccess<'de> for UnitVariantAccess<'_, 'de> {
+struct VariantDataAccess<'a, I, S> {
+    data: VariantData<I, S>,
+    config: &'a Config,
+}
+
+impl<'a, 'de, I, S> VariantDataAccess<'a, I, S>
+where
+    I: Input<'de>,
+    S: ExactSizeIterator<Item = I>,
+{
+    // The single value holding the data of a newtype or struct variant, and
+    // where it is in the input.
+    fn single(self, expected: &'static str) -> RResult<(Deserializer<'a, I>, Segment)> {
+        let (value, segment) = match self.data {
+            VariantData::Tagged(value, segment) => (value, segment),
+            VariantData::Fields(mut fields, first) if fields.len() == 1 => match fields.next() {
+                Some(value) => (value, Segment::Index(first)),
+                None => return Err(de::Error::invalid_length(0, &expected)),
+            },
+            VariantData::Fields(fields, _) => {
+                return Err(de::Error::invalid_length(fields.len(), &expected))
+            }
+            VariantData::Unit => return Err(de::Error::invalid_length(0, &expected)),
+        };
+        Ok((Deserializer::nested(value, self.config), segment))
+    }
+}
+
+impl<'de, I, S> de::VariantAccess<'de> for VariantDataAccess<'_, I, S>
+where
+    I: Input<'de>,
+    S: ExactSizeIterator<Item = I>,
+{
     type Error = Error;
 
     fn unit_variant(self) -> RResult<(), Error> {
-        Ok(())
+        match self.data {
+            VariantData::Unit => Ok(()),
+            VariantData::Tagged(value, _) if value.value().is_nil() => Ok(()),
+            VariantData::Tagged(..) => Err(de::Error::invalid_length(1, &"unit variant")),
+            VariantData::Fields(fields, _) if fields.len() == 0 => Ok(()),
+            VariantData::Fields(fields, _) => {
+                Err(de::Error::invalid_length(fields.len(), &"unit variant"))
+            }
+        }
     }
 
-    fn newtype_variant_seed<T>(self, _seed: T) -> RResult<T::Value, Error>
+    fn newtype_variant_seed<T>(self, seed: T) -> RResult<T::Value, Error>
     where
         T: de::DeserializeSeed<'de>,
     {
-        Err(de::Error::invalid_type(
-            Unexpected::UnitVariant,
-            &"newtype variant",
-        ))
+        let (de, segment) = self.single("newtype variant")?;
+        seed.deserialize(de).map_err(|e| e.at(segment))
     }
 
-    fn tuple_variant<V>(self, _len: usize, _visitor: V) -> RResult<V::Value, Error>
+    fn tuple_variant<V>(self, len: usize, visitor: V) -> RResult<V::Value, Error>
     where
         V: de::Visitor<'de>,
     {
-        Err(de::Error::invalid_type(
-            Unexpected::UnitVariant,
-            &"tuple variant",
-        ))
+        match self.data {
+            VariantData::Tagged(value, segment) => match value.into_seq() {
+                Ok(fields) => visit_array(fields, Some(len), 0, self.config, visitor),
+                Err(value) => Err(Error::type_error("tuple variant", value.value())),
+            }
+            .map_err(|e| e.at(segment)),
+            VariantData::Fields(fields, first) => {
+                visit_array(fields, Some(len), first, self.config, visitor)
+            }
+            VariantData::Unit => Err(de::Error::invalid_length(0, &"tuple variant")),
+        }
     }
 
     fn struct_variant<V>(
         self,
-        _fields: &'static [&'static str],
-        _visitor: V,
+        fields: &'static [&'static str],
+        visitor: V,
     ) -> RResult<V::Value, Error>
     where
         V: de::Visitor<'de>,
     {
-        Err(de::Error::invalid_type(
-            Unexpected::UnitVariant,
-            &"struct variant",
-        ))
+        let (de, segment) = self.single("struct variant")?;
+        de::Deserializer::deserialize_struct(de, "", fields, visitor).map_err(|e| e.at(segment))
     }
 }
 
@@ -680,6 +1351,505 @@ mod tests {
         let ext = rmpv::Value::Ext(42, vec![1, 2, 3]);
         let foo: Foo = from_value(&ext).unwrap();
         assert_eq!(Foo((42, vec![1, 2, 3])), foo);
+
+        // Negative tags don't fit the u8 tag, and are an error.
+        let ext = rmpv::Value::Ext(-1, vec![1, 2, 3]);
+        from_value::<Foo>(&ext).expect_err("expected range error");
+
+        #[serde_as]
+        #[derive(Deserialize, Debug, PartialEq)]
+        #[serde(rename = "_ExtStruct")]
+        struct Signed(#[serde_as(as = "(_, Bytes)")] (i8, Vec<u8>));
+
+        for tag in [-1, i8::MIN, i8::MAX] {
+            let ext = rmpv::Value::Ext(tag, vec![1]);
+            assert_eq!(Signed((tag, vec![1])), from_value(&ext).unwrap());
+        }
+
+        // Without `Bytes`, the data is read as a sequence of u8s.
+        #[derive(Deserialize, Debug, PartialEq)]
+        #[serde(rename = "_ExtStruct")]
+        struct Plain((i8, Vec<u8>));
+        #[derive(Deserialize, Debug, PartialEq)]
+        #[serde(rename = "_ExtStruct")]
+        struct Fixed((i8, [u8; 2]));
+
+        let ext = rmpv::Value::Ext(-3, vec![1, 2]);
+        assert_eq!(Plain((-3, vec![1, 2])), from_value(&ext).unwrap());
+        assert_eq!(
+            Plain((-3, vec![1, 2])),
+            from_value_owned(ext.clone()).unwrap()
+        );
+        assert_eq!(Fixed((-3, [1, 2])), from_value(&ext).unwrap());
+        let ext = rmpv::Value::Ext(-3, vec![1, 2, 3]);
+        from_value::<Fixed>(&ext).expect_err("expected length error");
+        for malformed in [
+            rmpv::Value::Nil,
+            rmpv::Value::from(1),
+            rmpv::Value::Array(vec![]),
+            rmpv::Value::Array(vec![rmpv::Value::from(200), rmpv::Value::Binary(vec![])]),
+            rmpv::Value::Array(vec![rmpv::Value::from(1), rmpv::Value::from(true)]),
+        ] {
+            from_value::<Signed>(&malformed).expect_err("expected malformed ext error");
+        }
+    }
+
+    #[test]
+    fn test_borrowed_bytes() {
+        #[derive(Deserialize, Debug, PartialEq)]
+        struct Frame<'a> {
+            data: &'a [u8],
+            raw: &'a [u8],
+        }
+
+        #[derive(Deserialize, Debug, PartialEq)]
+        #[serde(rename = "_ExtStruct")]
+        struct Ext<'a>(#[serde(borrow)] (i8, &'a [u8]));
+
+        let val = rmpv::Value::Map(vec![
+            ("data".into(), rmpv::Value::Binary(vec![1, 2, 3])),
+            // A str8 that is not valid UTF-8.
+            (
+                "raw".into(),
+                rmpv::decode::read_value(&mut &[0xa2, 0xff, 0xfe][..]).unwrap(),
+            ),
+        ]);
+        let frame: Frame = from_value(&val).unwrap();
+        assert_eq!(
+            Frame {
+                data: &[1, 2, 3],
+                raw: &[0xff, 0xfe]
+            },
+            frame
+        );
+        assert_eq!(
+            val["data"].as_slice().unwrap().as_ptr(),
+            frame.data.as_ptr()
+        );
+
+        let val = rmpv::Value::Ext(-3, vec![4, 5]);
+        let ext: Ext = from_value(&val).unwrap();
+        assert_eq!(Ext((-3, &[4, 5])), ext);
+    }
+
+    #[test]
+    fn test_struct_keys() {
+        #[derive(Deserialize, Debug, PartialEq)]
+        struct S {
+            a: u8,
+            b: Option<u8>,
+            c: String,
+        }
+
+        let val = rmpv::Value::Map(vec![
+            (0.into(), 1.into()),
+            (rmpv::Value::Binary(b"b".to_vec()), 2.into()),
+            ("c".into(), "c".into()),
+        ]);
+        let s = S {
+            a: 1,
+            b: Some(2),
+            c: "c".into(),
+        };
+        assert_eq!(s, from_value(&val).unwrap());
+        assert_eq!(s, from_value_owned(val).unwrap());
+
+        // Sparse, with the error path naming the field.
+        let val = rmpv::Value::Map(vec![(2.into(), "c".into()), (0.into(), 1.into())]);
+        let s: S = from_value(&val).unwrap();
+        assert_eq!((1, None), (s.a, s.b));
+        let val = rmpv::Value::Map(vec![(0.into(), "a".into())]);
+        let err = from_value::<S>(&val).unwrap_err();
+        assert_eq!(".a", err.path().unwrap().to_string());
+    }
+
+    #[test]
+    fn test_error_path() {
+        #[derive(Deserialize, Debug)]
+        #[allow(dead_code)]
+        struct Address {
+            zip: u32,
+        }
+
+        #[derive(Deserialize, Debug)]
+        #[allow(dead_code)]
+        struct User {
+            address: Address,
+        }
+
+        #[derive(Deserialize, Debug)]
+        #[allow(dead_code)]
+        struct Users {
+            users: Vec<User>,
+        }
+
+        let user = |zip: rmpv::Value| {
+            rmpv::Value::Map(vec![(
+                "address".into(),
+                rmpv::Value::Map(vec![("zip".into(), zip)]),
+            )])
+        };
+        let val = rmpv::Value::Map(vec![(
+            "users".into(),
+            rmpv::Value::Array(vec![user(1.into()), user("x".repeat(40).into())]),
+        )]);
+        let err = from_value::<Users>(&val).unwrap_err();
+        assert_eq!(".users[1].address.zip", err.path().unwrap().to_string());
+        assert_eq!(
+            format!(
+                "invalid type: string \"{}\"..., expected u32 at .users[1].address.zip",
+                "x".repeat(32)
+            ),
+            err.to_string()
+        );
+        assert_eq!(
+            &ErrorKind::InvalidType {
+                unexpected: Unexpected::Str("x".repeat(40)),
+                expected: "u32".into()
+            },
+            err.kind()
+        );
+
+        let err = from_value::<u32>(&rmpv::Value::from("x")).unwrap_err();
+        assert_eq!(None, err.path());
+    }
+
+    #[test]
+    fn test_variant_error_path() {
+        #[derive(Deserialize, Debug)]
+        #[allow(dead_code)]
+        enum E {
+            N(u8),
+            T(u8, u8),
+            S { a: u8 },
+        }
+
+        #[derive(Deserialize, Debug)]
+        #[allow(dead_code)]
+        struct Outer {
+            e: E,
+        }
+
+        let arr = |items: Vec<rmpv::Value>| rmpv::Value::Array(items);
+        let tagged = |key: rmpv::Value, data| rmpv::Value::Map(vec![(key, data)]);
+        let bad = || rmpv::Value::from("x");
+        let fields = || rmpv::Value::Map(vec![("a".into(), bad())]);
+        for (e, path) in [
+            // `EnumRepr::NamedArray`
+            (arr(vec!["E".into(), "N".into(), bad()]), ".e[2]"),
+            (arr(vec!["E".into(), "T".into(), 1.into(), bad()]), ".e[3]"),
+            (arr(vec!["E".into(), "S".into(), fields()]), ".e[2].a"),
+            // `EnumRepr::ExternallyTagged`
+            (tagged("N".into(), bad()), ".e.N"),
+            (tagged("T".into(), arr(vec![1.into(), bad()])), ".e.T[1]"),
+            (tagged("S".into(), fields()), ".e.S.a"),
+            // `EnumRepr::VariantIndex`
+            (tagged(0.into(), bad()), ".e.0"),
+            (tagged(1.into(), arr(vec![1.into(), bad()])), ".e.1[1]"),
+            (tagged(2.into(), fields()), ".e.2.a"),
+            // `EnumRepr::IndexArray`
+            (arr(vec![0.into(), bad()]), ".e[1]"),
+            (arr(vec![1.into(), 1.into(), bad()]), ".e[2]"),
+            (arr(vec![2.into(), fields()]), ".e[1].a"),
+        ] {
+            let val = rmpv::Value::Map(vec![("e".into(), e)]);
+            let err = from_value::<Outer>(&val).unwrap_err();
+            assert_eq!(path, err.path().unwrap().to_string());
+        }
+    }
+
+    #[test]
+    fn test_error_kind() {
+        #[derive(Deserialize, Debug)]
+        #[allow(dead_code)]
+        struct S {
+            a: Vec<u8>,
+            e: Option<E>,
+        }
+
+        #[derive(Deserialize, Debug)]
+        enum E {
+            A,
+        }
+
+        let map = |entries: Vec<(&str, rmpv::Value)>| {
+            rmpv::Value::Map(entries.into_iter().map(|(k, v)| (k.into(), v)).collect())
+        };
+
+        let err = from_value::<S>(&map(vec![("e", rmpv::Value::Nil)])).unwrap_err();
+        assert_eq!(&ErrorKind::MissingField("a"), err.kind());
+        assert_eq!("missing field `a`", err.to_string());
+
+        let val = map(vec![
+            ("a", rmpv::Value::Array(vec![1.into(), true.into()])),
+            ("e", rmpv::Value::Nil),
+        ]);
+        let err = from_value::<S>(&val).unwrap_err();
+        assert_eq!(
+            &ErrorKind::InvalidType {
+                unexpected: Unexpected::Bool(true),
+                expected: "u8".into()
+            },
+            err.kind()
+        );
+        assert_eq!(".a[1]", err.path().unwrap().to_string());
+
+        let val = map(vec![("a", rmpv::Value::Array(vec![])), ("e", "B".into())]);
+        let err = from_value::<S>(&val).unwrap_err();
+        assert_eq!(
+            &ErrorKind::UnknownVariant {
+                variant: "B".into(),
+                expected: &["A"]
+            },
+            err.kind()
+        );
+        assert_eq!("unknown variant `B`, expected `A` at .e", err.to_string());
+
+        let err = from_value::<(u8, u8)>(&rmpv::Value::Array(vec![1.into()])).unwrap_err();
+        assert!(matches!(
+            err.kind(),
+            ErrorKind::InvalidLength { len: 1, .. }
+        ));
+    }
+
+    #[test]
+    fn test_strict() {
+        #[derive(Deserialize, Debug, PartialEq)]
+        struct Pair(u8, u8);
+
+        #[derive(Deserialize, Debug, PartialEq)]
+        struct S {
+            a: u8,
+        }
+
+        #[derive(Deserialize, Debug, PartialEq)]
+        enum E {
+            Tuple(u8, u8),
+            Newtype(u8),
+        }
+
+        let strict = Config::new().with_strict(true);
+        let check = |val: rmpv::Value, ok: fn(&rmpv::Value, &Config) -> RResult<()>| {
+            ok(&val, &Config::new()).expect("lenient");
+            ok(&val, &strict).expect_err("strict")
+        };
+        fn tuple(v: &rmpv::Value, c: &Config) -> RResult<()> {
+            from_value_with::<(u8, u8)>(v, c).map(drop)
+        }
+        fn pair(v: &rmpv::Value, c: &Config) -> RResult<()> {
+            from_value_with::<Pair>(v, c).map(drop)
+        }
+        fn s(v: &rmpv::Value, c: &Config) -> RResult<()> {
+            from_value_with::<S>(v, c).map(drop)
+        }
+        fn e(v: &rmpv::Value, c: &Config) -> RResult<()> {
+            from_value_with::<E>(v, c).map(drop)
+        }
+
+        let three = rmpv::Value::Array(vec![1.into(), 2.into(), 3.into()]);
+        let err = check(three.clone(), tuple);
+        assert_eq!(
+            &ErrorKind::InvalidLength {
+                len: 3,
+                expected: "2 elements".into()
+            },
+            err.kind()
+        );
+        check(three.clone(), pair);
+        check(rmpv::Value::Array(vec![1.into(), 2.into()]), s);
+        let err = check(
+            rmpv::Value::Map(vec![(rmpv::Value::Binary(b"a".to_vec()), 1.into())]),
+            s,
+        );
+        assert!(matches!(err.kind(), ErrorKind::InvalidType { .. }));
+        check(rmpv::Value::Map(vec![("Tuple".into(), three.clone())]), e);
+        check(
+            rmpv::Value::Array(vec![
+                "E".into(),
+                "Tuple".into(),
+                1.into(),
+                2.into(),
+                3.into(),
+            ]),
+            e,
+        );
+        let err = check(
+            rmpv::Value::Array(vec!["F".into(), "Newtype".into(), 1.into()]),
+            e,
+        );
+        assert_eq!(
+            "invalid value: string \"F\", expected enum E",
+            err.to_string()
+        );
+
+        // Well-formed input, including integer field keys, is accepted.
+        let val = rmpv::Value::Array(vec!["E".into(), "Tuple".into(), 1.into(), 2.into()]);
+        assert_eq!(E::Tuple(1, 2), from_value_with(&val, &strict).unwrap());
+        let val = rmpv::Value::Map(vec![(0.into(), 1.into())]);
+        assert_eq!(S { a: 1 }, from_value_with(&val, &strict).unwrap());
+        let val = rmpv::Value::Array(vec![1.into()]);
+        assert_eq!(S { a: 1 }, from_value_with(&val, &strict).unwrap());
+
+        // Integer keys that can't be a field index are ignored unless strict.
+        let val = rmpv::Value::Map(vec![((-1).into(), 2.into()), ("a".into(), 1.into())]);
+        let err = check(val.clone(), s);
+        assert_eq!(Some(".-1"), err.path().map(ToString::to_string).as_deref());
+        assert_eq!(S { a: 1 }, from_value_with(&val, &Config::new()).unwrap());
+        let val = rmpv::Value::Map(vec![((-1).into(), 2.into())]);
+        from_value_with::<std::collections::BTreeMap<i8, u8>>(&val, &strict).unwrap();
+    }
+
+    #[test]
+    fn test_duplicate_keys() {
+        use std::collections::BTreeMap;
+
+        #[derive(Deserialize, Debug, PartialEq)]
+        struct S {
+            a: u8,
+        }
+
+        #[derive(Deserialize, Debug, PartialEq)]
+        struct Outer {
+            inner: Vec<S>,
+            map: BTreeMap<String, u8>,
+        }
+
+        let dup =
+            |key: rmpv::Value| rmpv::Value::Map(vec![("a".into(), 1.into()), (key, 2.into())]);
+        let outer = |inner: rmpv::Value, map: rmpv::Value| {
+            rmpv::Value::Map(vec![
+                ("inner".into(), rmpv::Value::Array(vec![inner])),
+                ("map".into(), map),
+            ])
+        };
+        let single = rmpv::Value::Map(vec![("a".into(), 1.into())]);
+
+        let config = |policy| Config::new().with_duplicate_key_policy(policy);
+        let read = |val: &rmpv::Value, policy| from_value_with::<Outer>(val, &config(policy));
+        let expect = |a: u8| Outer {
+            inner: vec![S { a }],
+            map: [("a".to_string(), a)].into_iter().collect(),
+        };
+
+        // Derived structs report duplicate fields, maps keep the last value.
+        let val = outer(dup("a".into()), single.clone());
+        read(&val, DuplicateKeyPolicy::Allow).expect_err("expected duplicate field");
+        let val = outer(single.clone(), dup("a".into()));
+        assert_eq!(
+            expect(2).map,
+            read(&val, DuplicateKeyPolicy::Allow).unwrap().map
+        );
+
+        // Struct fields are the same key whether named by string, binary or
+        // index.
+        for key in [
+            rmpv::Value::from("a"),
+            rmpv::Value::Binary(b"a".to_vec()),
+            rmpv::Value::from(0),
+        ] {
+            let val = outer(dup(key.clone()), dup("a".into()));
+            assert_eq!(
+                expect(1),
+                read(&val, DuplicateKeyPolicy::FirstWins).unwrap()
+            );
+            assert_eq!(expect(2), read(&val, DuplicateKeyPolicy::LastWins).unwrap());
+            let err = read(&val, DuplicateKeyPolicy::Error).unwrap_err();
+            assert_eq!(&ErrorKind::DuplicateKey("a".into()), err.kind());
+            assert_eq!(".inner[0].a", err.path().unwrap().to_string());
+        }
+        let val = outer(single.clone(), dup("a".into()));
+        let err = read(&val, DuplicateKeyPolicy::Error).unwrap_err();
+        assert_eq!("duplicate key `a` at .map.a", err.to_string());
+
+        // Keys are compared as MessagePack values, not by how they display.
+        let val = rmpv::Value::Map(vec![
+            ("1".into(), 1.into()),
+            (1.into(), 2.into()),
+            (rmpv::Value::Binary(b"1".to_vec()), 3.into()),
+        ]);
+        assert_eq!(
+            val,
+            from_value_with::<rmpv::Value>(&val, &config(DuplicateKeyPolicy::Error)).unwrap()
+        );
+    }
+
+    #[test]
+    fn test_integer_range() {
+        assert_eq!(255, from_value::<u8>(&rmpv::Value::from(255)).unwrap());
+        assert_eq!(-128, from_value::<i8>(&rmpv::Value::from(-128)).unwrap());
+        assert_eq!(
+            &ErrorKind::IntegerOutOfRange {
+                value: 300,
+                target: "u8"
+            },
+            from_value::<u8>(&rmpv::Value::from(300))
+                .unwrap_err()
+                .kind()
+        );
+        assert_eq!(
+            &ErrorKind::IntegerOutOfRange {
+                value: -1,
+                target: "u32"
+            },
+            from_value::<u32>(&rmpv::Value::from(-1))
+                .unwrap_err()
+                .kind()
+        );
+        from_value::<i64>(&rmpv::Value::from(u64::MAX)).expect_err("expected range error");
+
+        let wrapping = Config::new().with_integer_policy(IntegerPolicy::Wrapping);
+        assert_eq!(
+            44,
+            from_value_with::<u8>(&rmpv::Value::from(300), &wrapping).unwrap()
+        );
+        assert_eq!(
+            u32::MAX,
+            from_value_with::<u32>(&rmpv::Value::from(-1), &wrapping).unwrap()
+        );
+    }
+
+    #[test]
+    fn test_deserialize_any() {
+        #[serde_as]
+        #[derive(Deserialize, Debug, PartialEq)]
+        #[serde(rename = "_ExtStruct")]
+        struct Ext(#[serde_as(as = "(_, Bytes)")] (i8, Vec<u8>));
+
+        #[derive(Deserialize, Debug, PartialEq)]
+        #[serde(untagged)]
+        enum Any {
+            Unsigned(u64),
+            Signed(i64),
+            Float(f64),
+            Ext(Ext),
+        }
+
+        assert_eq!(
+            Any::Unsigned(u64::MAX),
+            from_value(&rmpv::Value::from(u64::MAX)).unwrap()
+        );
+        assert_eq!(Any::Signed(-1), from_value(&rmpv::Value::from(-1)).unwrap());
+        assert_eq!(Any::Float(1.5), from_value(&rmpv::Value::F32(1.5)).unwrap());
+        assert_eq!(Any::Float(2.5), from_value(&rmpv::Value::F64(2.5)).unwrap());
+        assert_eq!(
+            Any::Ext(Ext((5, vec![1, 2]))),
+            from_value(&rmpv::Value::Ext(5, vec![1, 2])).unwrap()
+        );
+
+        // Unknown fields of any type are skipped
+        #[derive(Deserialize, Debug, PartialEq)]
+        struct S {
+            a: u8,
+        }
+        assert_eq!(
+            S { a: 1 },
+            from_value(&rmpv::Value::Map(vec![
+                (rmpv::Value::from("a"), rmpv::Value::from(1)),
+                (rmpv::Value::from("f"), rmpv::Value::F64(1.0)),
+                (rmpv::Value::from("e"), rmpv::Value::Ext(1, vec![])),
+            ]))
+            .unwrap()
+        );
     }
 
     #[test]
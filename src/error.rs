@@ -4,34 +4,424 @@ use serde::{de, ser};
 
 pub type RResult<T, E = Error> = std::result::Result<T, E>;
 
+/// An error produced while converting to or from `rmpv::Value`.
+///
+/// The [`ErrorKind`] says what went wrong, and the [`Path`] where in the
+/// input it happened.
 #[derive(thiserror::Error, Debug)]
-pub enum Error {
-    /// Type mismatch error
-    TypeError(String),
-    /// Data format error
-    Format(String),
-    /// Unsupported type
-    UnsupportedType,
+pub struct Error {
+    kind: ErrorKind,
+    path: Path,
+}
+
+impl Error {
+    /// What went wrong.
+    pub fn kind(&self) -> &ErrorKind {
+        &self.kind
+    }
+
+    /// Consumes the error, returning what went wrong.
+    pub fn into_kind(self) -> ErrorKind {
+        self.kind
+    }
+
+    /// The location in the input of the value that caused the error, if it
+    /// was nested inside an array or map.
+    pub fn path(&self) -> Option<&Path> {
+        if self.path.0.is_empty() {
+            None
+        } else {
+            Some(&self.path)
+        }
+    }
+
+    // A type error for `value`, which was expected to be `expected`.
+    pub(crate) fn type_error(expected: &str, value: &rmpv::Value) -> Self {
+        ErrorKind::InvalidType {
+            unexpected: Unexpected::from(value),
+            expected: expected.to_string(),
+        }
+        .into()
+    }
+
+    // Record that this error occurred within `segment` of the enclosing value.
+    pub(crate) fn at(mut self, segment: Segment) -> Self {
+        self.path.0.insert(0, segment);
+        self
+    }
+
+    // Convert to the error type of another deserializer through the
+    // `de::Error` constructors, so that the kind survives when that type is
+    // `Error`. Kinds without a constructor become a message.
+    pub(crate) fn into_de_error<E: de::Error>(self) -> E {
+        match &self.kind {
+            ErrorKind::InvalidType {
+                unexpected,
+                expected,
+            } => E::invalid_type(unexpected.as_de(), &expected.as_str()),
+            ErrorKind::InvalidValue {
+                unexpected,
+                expected,
+            } => E::invalid_value(unexpected.as_de(), &expected.as_str()),
+            ErrorKind::InvalidLength { len, expected } => {
+                E::invalid_length(*len, &expected.as_str())
+            }
+            ErrorKind::UnknownVariant { variant, expected } => {
+                E::unknown_variant(variant, expected)
+            }
+            ErrorKind::UnknownField { field, expected } => E::unknown_field(field, expected),
+            ErrorKind::MissingField(field) => E::missing_field(field),
+            ErrorKind::DuplicateField(field) => E::duplicate_field(field),
+            _ => E::custom(self),
+        }
+    }
+}
+
+impl From<ErrorKind> for Error {
+    fn from(kind: ErrorKind) -> Self {
+        Error {
+            kind,
+            path: Path::default(),
+        }
+    }
+}
+
+/// The kinds of [`Error`]. These follow the error constructors of
+/// `serde::de::Error`, plus the conditions specific to this crate.
+#[derive(Debug, Clone, PartialEq)]
+#[non_exhaustive]
+pub enum ErrorKind {
+    /// The input was of the wrong type
+    InvalidType {
+        unexpected: Unexpected,
+        expected: String,
+    },
+    /// The input was of the right type, but an invalid value
+    InvalidValue {
+        unexpected: Unexpected,
+        expected: String,
+    },
+    /// A sequence or map had the wrong number of elements
+    InvalidLength { len: usize, expected: String },
+    /// An enum variant that the target type does not have
+    UnknownVariant {
+        variant: String,
+        expected: &'static [&'static str],
+    },
+    /// A struct field that the target type does not have
+    UnknownField {
+        field: String,
+        expected: &'static [&'static str],
+    },
+    /// A struct field was missing from the input
+    MissingField(&'static str),
+    /// A struct field appeared more than once in the input
+    DuplicateField(&'static str),
+    /// Integer does not fit in the target type
+    IntegerOutOfRange { value: i128, target: &'static str },
+    /// A 128-bit integer, in decimal, that doesn't fit a MessagePack integer,
+    /// with [`Int128Fallback::Error`](crate::Int128Fallback::Error)
+    Int128OutOfRange(String),
+    /// A map key that occurs more than once, with
+    /// [`DuplicateKeyPolicy::Error`](crate::DuplicateKeyPolicy::Error)
+    DuplicateKey(String),
+    /// A `_ExtStruct` newtype that doesn't hold a `(tag, binary)` pair
+    InvalidExtStruct,
+    /// Any other error, such as a custom message from a `Serialize` or
+    /// `Deserialize` implementation
+    Message(String),
+}
+
+/// An owned version of `serde::de::Unexpected`: the kind of value that was
+/// found where something else was expected.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Unexpected {
+    Bool(bool),
+    Unsigned(u64),
+    Signed(i64),
+    Float(f64),
+    Char(char),
+    Str(String),
+    Bytes(Vec<u8>),
+    Unit,
+    Option,
+    NewtypeStruct,
+    Seq,
+    Map,
+    Enum,
+    UnitVariant,
+    NewtypeVariant,
+    TupleVariant,
+    StructVariant,
+    Other(String),
+}
+
+impl From<de::Unexpected<'_>> for Unexpected {
+    fn from(unexpected: de::Unexpected<'_>) -> Self {
+        match unexpected {
+            de::Unexpected::Bool(v) => Unexpected::Bool(v),
+            de::Unexpected::Unsigned(v) => Unexpected::Unsigned(v),
+            de::Unexpected::Signed(v) => Unexpected::Signed(v),
+            de::Unexpected::Float(v) => Unexpected::Float(v),
+            de::Unexpected::Char(v) => Unexpected::Char(v),
+            de::Unexpected::Str(v) => Unexpected::Str(v.to_string()),
+            de::Unexpected::Bytes(v) => Unexpected::Bytes(v.to_vec()),
+            de::Unexpected::Unit => Unexpected::Unit,
+            de::Unexpected::Option => Unexpected::Option,
+            de::Unexpected::NewtypeStruct => Unexpected::NewtypeStruct,
+            de::Unexpected::Seq => Unexpected::Seq,
+            de::Unexpected::Map => Unexpected::Map,
+            de::Unexpected::Enum => Unexpected::Enum,
+            de::Unexpected::UnitVariant => Unexpected::UnitVariant,
+            de::Unexpected::NewtypeVariant => Unexpected::NewtypeVariant,
+            de::Unexpected::TupleVariant => Unexpected::TupleVariant,
+            de::Unexpected::StructVariant => Unexpected::StructVariant,
+            de::Unexpected::Other(v) => Unexpected::Other(v.to_string()),
+        }
+    }
+}
+
+impl From<&rmpv::Value> for Unexpected {
+    fn from(value: &rmpv::Value) -> Self {
+        match value {
+            rmpv::Value::Nil => Unexpected::Unit,
+            rmpv::Value::Boolean(v) => Unexpected::Bool(*v),
+            rmpv::Value::Integer(i) => match i.as_u64() {
+                Some(v) => Unexpected::Unsigned(v),
+                None => Unexpected::Signed(i.as_i64().unwrap_or_default()),
+            },
+            rmpv::Value::F32(v) => Unexpected::Float(f64::from(*v)),
+            rmpv::Value::F64(v) => Unexpected::Float(*v),
+            rmpv::Value::String(s) => Unexpected::Str(String::from_utf8_lossy(s.as_bytes()).into()),
+            rmpv::Value::Binary(b) => Unexpected::Bytes(b.clone()),
+            rmpv::Value::Array(_) => Unexpected::Seq,
+            rmpv::Value::Map(_) => Unexpected::Map,
+            rmpv::Value::Ext(tag, _) => Unexpected::Other(format!("ext type {}", tag)),
+        }
+    }
+}
+
+impl Unexpected {
+    fn as_de(&self) -> de::Unexpected<'_> {
+        match self {
+            Unexpected::Bool(v) => de::Unexpected::Bool(*v),
+            Unexpected::Unsigned(v) => de::Unexpected::Unsigned(*v),
+            Unexpected::Signed(v) => de::Unexpected::Signed(*v),
+            Unexpected::Float(v) => de::Unexpected::Float(*v),
+            Unexpected::Char(v) => de::Unexpected::Char(*v),
+            Unexpected::Str(v) => de::Unexpected::Str(v),
+            Unexpected::Bytes(v) => de::Unexpected::Bytes(v),
+            Unexpected::Unit => de::Unexpected::Unit,
+            Unexpected::Option => de::Unexpected::Option,
+            Unexpected::NewtypeStruct => de::Unexpected::NewtypeStruct,
+            Unexpected::Seq => de::Unexpected::Seq,
+            Unexpected::Map => de::Unexpected::Map,
+            Unexpected::Enum => de::Unexpected::Enum,
+            Unexpected::UnitVariant => de::Unexpected::UnitVariant,
+            Unexpected::NewtypeVariant => de::Unexpected::NewtypeVariant,
+            Unexpected::TupleVariant => de::Unexpected::TupleVariant,
+            Unexpected::StructVariant => de::Unexpected::StructVariant,
+            Unexpected::Other(v) => de::Unexpected::Other(v),
+        }
+    }
+}
+
+impl Display for Unexpected {
+    fn fmt(&self, formatter: &mut fmt::Formatter) -> fmt::Result {
+        // Long strings are shortened so that errors stay readable in logs.
+        const MAX: usize = 32;
+        match self {
+            Unexpected::Str(v) => match v.char_indices().nth(MAX) {
+                Some((i, _)) => write!(formatter, "string {:?}...", &v[..i]),
+                None => Display::fmt(&self.as_de(), formatter),
+            },
+            _ => Display::fmt(&self.as_de(), formatter),
+        }
+    }
+}
+
+/// The location of a value within an `rmpv::Value`, displayed as e.g.
+/// `.users[3].address.zip`.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Path(Vec<Segment>);
+
+impl Path {
+    /// The segments of the path, outermost first.
+    pub fn segments(&self) -> &[Segment] {
+        &self.0
+    }
+}
+
+impl Display for Path {
+    fn fmt(&self, formatter: &mut fmt::Formatter) -> fmt::Result {
+        for segment in &self.0 {
+            match segment {
+                Segment::Index(i) => write!(formatter, "[{}]", i)?,
+                Segment::Key(k) => write!(formatter, ".{}", k)?,
+            }
+        }
+        Ok(())
+    }
+}
+
+/// A single step in a [`Path`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Segment {
+    /// An element of an array
+    Index(usize),
+    /// The value of a map entry, with the key rendered as a string
+    Key(String),
 }
 
 impl ser::Error for Error {
     fn custom<T: Display>(msg: T) -> Self {
-        Error::Format(msg.to_string())
+        ErrorKind::Message(msg.to_string()).into()
     }
 }
 
 impl de::Error for Error {
     fn custom<T: Display>(msg: T) -> Self {
-        Error::Format(msg.to_string())
+        ErrorKind::Message(msg.to_string()).into()
+    }
+
+    fn invalid_type(unexp: de::Unexpected, exp: &dyn de::Expected) -> Self {
+        ErrorKind::InvalidType {
+            unexpected: unexp.into(),
+            expected: exp.to_string(),
+        }
+        .into()
+    }
+
+    fn invalid_value(unexp: de::Unexpected, exp: &dyn de::Expected) -> Self {
+        ErrorKind::InvalidValue {
+            unexpected: unexp.into(),
+            expected: exp.to_string(),
+        }
+        .into()
+    }
+
+    fn invalid_length(len: usize, exp: &dyn de::Expected) -> Self {
+        ErrorKind::InvalidLength {
+            len,
+            expected: exp.to_string(),
+        }
+        .into()
+    }
+
+    fn unknown_variant(variant: &str, expected: &'static [&'static str]) -> Self {
+        ErrorKind::UnknownVariant {
+            variant: variant.to_string(),
+            expected,
+        }
+        .into()
+    }
+
+    fn unknown_field(field: &str, expected: &'static [&'static str]) -> Self {
+        ErrorKind::UnknownField {
+            field: field.to_string(),
+            expected,
+        }
+        .into()
+    }
+
+    fn missing_field(field: &'static str) -> Self {
+        ErrorKind::MissingField(field).into()
+    }
+
+    fn duplicate_field(field: &'static str) -> Self {
+        ErrorKind::DuplicateField(field).into()
     }
 }
 
 impl Display for Error {
+    fn fmt(&self, formatter: &mut fmt::Formatter) -> fmt::Result {
+        match self.path() {
+            Some(path) => write!(formatter, "{} at {}", self.kind, path),
+            None => write!(formatter, "{}", self.kind),
+        }
+    }
+}
+
+// Formats a list of names as serde does: "`a`, `b` or `c`".
+struct OneOf(&'static [&'static str]);
+
+impl Display for OneOf {
+    fn fmt(&self, formatter: &mut fmt::Formatter) -> fmt::Result {
+        match self.0 {
+            [] => Ok(()),
+            [a] => write!(formatter, "`{}`", a),
+            [a, b] => write!(formatter, "`{}` or `{}`", a, b),
+            [rest @ .., last] => {
+                for name in rest {
+                    write!(formatter, "`{}`, ", name)?;
+                }
+                write!(formatter, "or `{}`", last)
+            }
+        }
+    }
+}
+
+impl Display for ErrorKind {
     fn fmt(&self, formatter: &mut fmt::Formatter) -> fmt::Result {
         match self {
-            Error::TypeError(msg) => write!(formatter, "invalid type: {}", msg),
-            Error::Format(msg) => write!(formatter, "{}", msg),
-            Error::UnsupportedType => write!(formatter, "unsupported type"),
+            ErrorKind::InvalidType {
+                unexpected,
+                expected,
+            } => write!(
+                formatter,
+                "invalid type: {}, expected {}",
+                unexpected, expected
+            ),
+            ErrorKind::InvalidValue {
+                unexpected,
+                expected,
+            } => write!(
+                formatter,
+                "invalid value: {}, expected {}",
+                unexpected, expected
+            ),
+            ErrorKind::InvalidLength { len, expected } => {
+                write!(formatter, "invalid length {}, expected {}", len, expected)
+            }
+            ErrorKind::UnknownVariant {
+                variant,
+                expected: [],
+            } => write!(
+                formatter,
+                "unknown variant `{}`, there are no variants",
+                variant
+            ),
+            ErrorKind::UnknownVariant { variant, expected } => write!(
+                formatter,
+                "unknown variant `{}`, expected {}",
+                variant,
+                OneOf(expected)
+            ),
+            ErrorKind::UnknownField {
+                field,
+                expected: [],
+            } => {
+                write!(formatter, "unknown field `{}`, there are no fields", field)
+            }
+            ErrorKind::UnknownField { field, expected } => write!(
+                formatter,
+                "unknown field `{}`, expected {}",
+                field,
+                OneOf(expected)
+            ),
+            ErrorKind::MissingField(field) => write!(formatter, "missing field `{}`", field),
+            ErrorKind::DuplicateField(field) => write!(formatter, "duplicate field `{}`", field),
+            ErrorKind::IntegerOutOfRange { value, target } => {
+                write!(formatter, "integer {} out of range for {}", value, target)
+            }
+            ErrorKind::Int128OutOfRange(value) => write!(
+                formatter,
+                "integer {} does not fit in a MessagePack integer",
+                value
+            ),
+            ErrorKind::DuplicateKey(key) => write!(formatter, "duplicate key `{}`", key),
+            ErrorKind::InvalidExtStruct => write!(formatter, "invalid ext struct"),
+            ErrorKind::Message(msg) => write!(formatter, "{}", msg),
         }
     }
 }
@@ -0,0 +1,381 @@
+use std::{
+    any::{Any, TypeId},
+    fmt::{self, Display},
+    sync::Arc,
+};
+
+use serde::{
+    de::{self, DeserializeOwned, SeqAccess, Visitor},
+    ser::{self, SerializeTuple},
+    Deserialize, Deserializer, Serialize, Serializer,
+};
+
+use crate::{
+    config::Config,
+    error::{Error, RResult},
+    MSGPACK_EXT_STRUCT_NAME,
+};
+
+type EncodeFn = dyn Fn(&dyn Any) -> Option<Vec<u8>> + Send + Sync;
+type DecodeFn = dyn Fn(&[u8], &Config) -> RResult<rmpv::Value> + Send + Sync;
+
+#[derive(Clone)]
+pub(crate) struct ExtEntry {
+    tag: i8,
+    type_id: TypeId,
+    type_name: &'static str,
+    encode: Arc<EncodeFn>,
+    decode: Arc<DecodeFn>,
+}
+
+impl ExtEntry {
+    // Decode Ext data into the plain serialized form of the registered type.
+    pub(crate) fn decode(&self, data: &[u8], config: &Config) -> RResult<rmpv::Value> {
+        (self.decode)(data, config)
+    }
+}
+
+/// A registry mapping Ext tags to Rust types, so that values of those types
+/// are written as `Value::Ext` and read back without an `_ExtStruct` wrapper.
+///
+/// When serializing, a registered type is written as an Ext value where it is
+/// marked with [`Registered`](crate::Registered) or
+/// `#[serde(with = "serde_rmpv::registered")]`, and as usual elsewhere. When
+/// deserializing, an Ext value with a registered tag is decoded and then read as the registered
+/// type's usual serialized form, wherever the target asks for a specific type.
+/// Targets that accept any value, such as `rmpv::Value` and untagged enums,
+/// get the Ext value unchanged. Pass the registry in with
+/// [`Config::with_ext_registry`].
+///
+/// # Example
+/// ```rust
+/// use serde_rmpv::{Config, ExtRegistry, Registered};
+///
+/// #[derive(serde_derive::Serialize, serde_derive::Deserialize, Debug, PartialEq)]
+/// struct Point(u8, u8);
+///
+/// let registry = ExtRegistry::new().register(
+///     5,
+///     |p: &Point| vec![p.0, p.1],
+///     |data: &[u8]| match data {
+///         [x, y] => Ok(Point(*x, *y)),
+///         _ => Err("expected 2 bytes"),
+///     },
+/// );
+/// let config = Config::new().with_ext_registry(registry);
+///
+/// let value = serde_rmpv::to_value_with(&vec![Registered(Point(1, 2))], &config).unwrap();
+/// assert_eq!(rmpv::Value::Array(vec![rmpv::Value::Ext(5, vec![1, 2])]), value);
+/// let points: Vec<Point> = serde_rmpv::from_value_with(&value, &config).unwrap();
+/// assert_eq!(vec![Point(1, 2)], points);
+/// ```
+#[derive(Clone, Default)]
+pub struct ExtRegistry {
+    entries: Arc<Vec<ExtEntry>>,
+}
+
+impl ExtRegistry {
+    /// Creates an empty registry.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Registers `T` as the Ext type `tag`, encoded and decoded with the given
+    /// functions. Registering a tag or type again replaces the earlier entry.
+    ///
+    /// Marked values are recognised by the `TypeId` of `T` and handed to
+    /// `encode` as they are.
+    pub fn register<T, E, D, Err>(mut self, tag: i8, encode: E, decode: D) -> Self
+    where
+        T: Serialize + 'static,
+        E: Fn(&T) -> Vec<u8> + Send + Sync + 'static,
+        D: Fn(&[u8]) -> Result<T, Err> + Send + Sync + 'static,
+        Err: Display,
+    {
+        let type_id = TypeId::of::<T>();
+        let entries = Arc::make_mut(&mut self.entries);
+        entries.retain(|e| e.tag != tag && e.type_id != type_id);
+        entries.push(ExtEntry {
+            tag,
+            type_id,
+            type_name: std::any::type_name::<T>(),
+            encode: Arc::new(move |value| value.downcast_ref::<T>().map(&encode)),
+            decode: Arc::new(move |data, config| {
+                let v = decode(data).map_err(<Error as de::Error>::custom)?;
+                crate::to_value_with(&v, config)
+            }),
+        });
+        self
+    }
+
+    /// Returns true if no types are registered.
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+
+    // The tag and data of `value` as an Ext value, if its type is registered.
+    pub(crate) fn encode<T: 'static>(&self, value: &T) -> Option<(i8, Vec<u8>)> {
+        let entry = self
+            .entries
+            .iter()
+            .find(|e| e.type_id == TypeId::of::<T>())?;
+        (entry.encode)(value).map(|data| (entry.tag, data))
+    }
+
+    pub(crate) fn by_tag(&self, tag: i8) -> Option<&ExtEntry> {
+        self.entries.iter().find(|e| e.tag == tag)
+    }
+}
+
+impl fmt::Debug for ExtRegistry {
+    fn fmt(&self, formatter: &mut fmt::Formatter) -> fmt::Result {
+        formatter
+            .debug_map()
+            .entries(self.entries.iter().map(|e| (e.tag, e.type_name)))
+            .finish()
+    }
+}
+
+// Registries are equal if they map the same tags to the same types.
+impl PartialEq for ExtRegistry {
+    fn eq(&self, other: &Self) -> bool {
+        self.entries.len() == other.entries.len()
+            && self
+                .entries
+                .iter()
+                .zip(other.entries.iter())
+                .all(|(a, b)| a.tag == b.tag && a.type_id == b.type_id)
+    }
+}
+
+impl Eq for ExtRegistry {}
+
+/// An Ext value with tag `TAG`, whose payload is `T` encoded as MessagePack.
+///
+/// `T` is converted with [`to_value`](crate::to_value) and
+/// [`from_value_owned`](crate::from_value_owned), so it follows this crate's
+/// default enum and struct conventions regardless of the configuration of the
+/// enclosing serializer.
+///
+/// # Example
+/// ```rust
+/// use serde_rmpv::ExtEncoded;
+///
+/// let envelope: ExtEncoded<3, (u8, String)> = ExtEncoded((1, "body".into()));
+/// let value = serde_rmpv::to_value(&envelope).unwrap();
+/// assert!(matches!(value, rmpv::Value::Ext(3, _)));
+/// assert_eq!(envelope, serde_rmpv::from_value(&value).unwrap());
+/// ```
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct ExtEncoded<const TAG: i8, T>(pub T);
+
+impl<const TAG: i8, T> ExtEncoded<TAG, T> {
+    /// Consumes the wrapper, returning the payload.
+    pub fn into_inner(self) -> T {
+        self.0
+    }
+}
+
+impl<const TAG: i8, T: Serialize> Serialize for ExtEncoded<TAG, T> {
+    fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
+    where
+        S: Serializer,
+    {
+        let value = crate::to_value(&self.0).map_err(ser::Error::custom)?;
+        let mut data = Vec::new();
+        rmpv::encode::write_value(&mut data, &value).map_err(ser::Error::custom)?;
+        serialize_ext(serializer, TAG, &data)
+    }
+}
+
+impl<'de, const TAG: i8, T: DeserializeOwned> Deserialize<'de> for ExtEncoded<TAG, T> {
+    fn deserialize<D>(deserializer: D) -> Result<Self, D::Error>
+    where
+        D: Deserializer<'de>,
+    {
+        let data = deserialize_ext_data(deserializer, TAG)?;
+        let mut rest = data.as_slice();
+        let value = rmpv::decode::read_value(&mut rest).map_err(de::Error::custom)?;
+        if !rest.is_empty() {
+            return Err(de::Error::invalid_length(
+                data.len(),
+                &"a single MessagePack value as ext data",
+            ));
+        }
+        crate::from_value_owned(value)
+            .map(ExtEncoded)
+            .map_err(Error::into_de_error)
+    }
+}
+
+/// Serializes an Ext value with the given tag and data, through the
+/// `_ExtStruct((tag, binary))` convention.
+pub fn serialize_ext<S>(serializer: S, tag: i8, data: &[u8]) -> Result<S::Ok, S::Error>
+where
+    S: Serializer,
+{
+    serializer.serialize_newtype_struct(MSGPACK_EXT_STRUCT_NAME, &ExtData(tag, data))
+}
+
+/// Deserializes an Ext value through the `_ExtStruct((tag, binary))`
+/// convention, returning its tag and data.
+pub fn deserialize_ext<'de, D>(deserializer: D) -> Result<(i8, Vec<u8>), D::Error>
+where
+    D: Deserializer<'de>,
+{
+    deserializer.deserialize_newtype_struct(MSGPACK_EXT_STRUCT_NAME, ExtVisitor)
+}
+
+/// Deserializes the data of an Ext value, which must have the given tag.
+pub fn deserialize_ext_data<'de, D>(deserializer: D, tag: i8) -> Result<Vec<u8>, D::Error>
+where
+    D: Deserializer<'de>,
+{
+    match deserialize_ext(deserializer)? {
+        (t, data) if t == tag => Ok(data),
+        (t, _) => Err(de::Error::invalid_value(
+            de::Unexpected::Signed(t.into()),
+            &format!("ext tag {}", tag).as_str(),
+        )),
+    }
+}
+
+// The `(tag, binary)` pair inside an `_ExtStruct`.
+struct ExtData<'a>(i8, &'a [u8]);
+
+impl Serialize for ExtData<'_> {
+    fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
+    where
+        S: Serializer,
+    {
+        let mut tuple = serializer.serialize_tuple(2)?;
+        tuple.serialize_element(&self.0)?;
+        tuple.serialize_element(&Bytes(self.1))?;
+        tuple.end()
+    }
+}
+
+struct Bytes<'a>(&'a [u8]);
+
+impl Serialize for Bytes<'_> {
+    fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
+    where
+        S: Serializer,
+    {
+        serializer.serialize_bytes(self.0)
+    }
+}
+
+struct ExtVisitor;
+
+impl<'de> Visitor<'de> for ExtVisitor {
+    type Value = (i8, Vec<u8>);
+
+    fn expecting(&self, formatter: &mut fmt::Formatter) -> fmt::Result {
+        formatter.write_str("an ext value")
+    }
+
+    fn visit_newtype_struct<D>(self, deserializer: D) -> Result<Self::Value, D::Error>
+    where
+        D: Deserializer<'de>,
+    {
+        deserializer.deserialize_tuple(2, self)
+    }
+
+    fn visit_seq<A>(self, mut seq: A) -> Result<Self::Value, A::Error>
+    where
+        A: SeqAccess<'de>,
+    {
+        let tag = seq
+            .next_element()?
+            .ok_or_else(|| de::Error::invalid_length(0, &self))?;
+        let ByteBuf(data) = seq
+            .next_element()?
+            .ok_or_else(|| de::Error::invalid_length(1, &self))?;
+        Ok((tag, data))
+    }
+}
+
+struct ByteBuf(Vec<u8>);
+
+impl<'de> Deserialize<'de> for ByteBuf {
+    fn deserialize<D>(deserializer: D) -> Result<Self, D::Error>
+    where
+        D: Deserializer<'de>,
+    {
+        deserializer.deserialize_byte_buf(ByteBufVisitor)
+    }
+}
+
+struct ByteBufVisitor;
+
+impl<'de> Visitor<'de> for ByteBufVisitor {
+    type Value = ByteBuf;
+
+    fn expecting(&self, formatter: &mut fmt::Formatter) -> fmt::Result {
+        formatter.write_str("ext data")
+    }
+
+    fn visit_bytes<E: de::Error>(self, v: &[u8]) -> Result<Self::Value, E> {
+        Ok(ByteBuf(v.to_vec()))
+    }
+
+    fn visit_byte_buf<E: de::Error>(self, v: Vec<u8>) -> Result<Self::Value, E> {
+        Ok(ByteBuf(v))
+    }
+
+    fn visit_seq<A>(self, mut seq: A) -> Result<Self::Value, A::Error>
+    where
+        A: SeqAccess<'de>,
+    {
+        let mut data = Vec::with_capacity(seq.size_hint().unwrap_or(0));
+        while let Some(b) = seq.next_element()? {
+            data.push(b);
+        }
+        Ok(ByteBuf(data))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use serde_derive::{Deserialize, Serialize};
+
+    use crate::{from_value, to_value, ErrorKind};
+
+    #[test]
+    fn test_ext_encoded() {
+        #[derive(Serialize, Deserialize, Debug, PartialEq)]
+        enum Envelope {
+            Ping,
+            Data { id: u32, body: Vec<String> },
+        }
+
+        for e in [
+            Envelope::Ping,
+            Envelope::Data {
+                id: 1,
+                body: vec!["a".into()],
+            },
+        ] {
+            let ext: ExtEncoded<-5, Envelope> = ExtEncoded(e);
+            let val = to_value(&ext).unwrap();
+            let rmpv::Value::Ext(-5, data) = &val else {
+                panic!("expected ext: {}", val);
+            };
+            let inner = rmpv::decode::read_value(&mut data.as_slice()).unwrap();
+            assert_eq!(inner, to_value(&ext.0).unwrap());
+            assert_eq!(ext, from_value(&val).unwrap());
+        }
+
+        let val = to_value(&ExtEncoded::<1, _>(5u8)).unwrap();
+        from_value::<ExtEncoded<2, u8>>(&val).expect_err("expected tag error");
+        let err = from_value::<ExtEncoded<1, String>>(&val).unwrap_err();
+        assert!(matches!(err.kind(), ErrorKind::InvalidType { .. }));
+        from_value::<ExtEncoded<1, u8>>(&rmpv::Value::Ext(1, vec![5, 6]))
+            .expect_err("expected trailing data error");
+        from_value::<ExtEncoded<1, u8>>(&rmpv::Value::Ext(1, vec![]))
+            .expect_err("expected truncated data error");
+    }
+}
@@ -0,0 +1,178 @@
+use std::{borrow::Cow, fmt};
+
+use serde::{de::Visitor, Deserialize, Deserializer, Serialize, Serializer};
+
+use crate::{config::Config, error::Error};
+
+/// Name of the newtype struct through which [`LazyValue`] asks the
+/// deserializer for the input as is.
+pub(crate) const LAZY_VALUE_STRUCT_NAME: &str = "$serde_rmpv::private::LazyValue";
+
+/// A value whose deserialization is deferred.
+///
+/// A `LazyValue` field captures its part of the input without interpreting
+/// it, so it can be deserialized later once the target type is known. Ext
+/// types in an [`ExtRegistry`](crate::ExtRegistry) are left encoded until
+/// then. Serializing a `LazyValue` writes the captured value back unchanged.
+///
+/// Deserialization captures a copy of the input, which moves rather than
+/// copies strings and binary data with
+/// [`from_value_owned`](crate::from_value_owned). A `LazyValue` built from a
+/// `&rmpv::Value` borrows it.
+///
+/// # Example
+/// ```rust
+/// use serde_derive::Deserialize;
+/// use serde_rmpv::LazyValue;
+///
+/// #[derive(Deserialize)]
+/// struct Request<'a> {
+///     method: String,
+///     params: LazyValue<'a>,
+/// }
+///
+/// let val = rmpv::Value::Map(vec![
+///     ("method".into(), "add".into()),
+///     ("params".into(), vec![rmpv::Value::from(1), rmpv::Value::from(2)].into()),
+/// ]);
+/// let req: Request = serde_rmpv::from_value(&val).unwrap();
+/// let sum = match req.method.as_str() {
+///     "add" => {
+///         let (a, b): (u8, u8) = req.params.deserialize().unwrap();
+///         a + b
+///     }
+///     _ => unimplemented!(),
+/// };
+/// assert_eq!(3, sum);
+/// ```
+#[derive(Debug, Clone, PartialEq)]
+pub struct LazyValue<'a>(Cow<'a, rmpv::Value>);
+
+impl<'a> LazyValue<'a> {
+    /// Returns the captured value.
+    pub fn as_value(&self) -> &rmpv::Value {
+        &self.0
+    }
+
+    /// Returns the captured value, cloning it if it is borrowed.
+    pub fn into_value(self) -> rmpv::Value {
+        self.0.into_owned()
+    }
+
+    /// Returns a `LazyValue` that owns the captured value.
+    pub fn into_owned(self) -> LazyValue<'static> {
+        LazyValue(Cow::Owned(self.0.into_owned()))
+    }
+
+    /// Deserializes the captured value with the default configuration.
+    pub fn deserialize<'de, T>(&'de self) -> Result<T, Error>
+    where
+        T: Deserialize<'de>,
+    {
+        crate::from_value(&self.0)
+    }
+
+    /// Deserializes the captured value with the given configuration.
+    pub fn deserialize_with<'de, T>(&'de self, config: &Config) -> Result<T, Error>
+    where
+        T: Deserialize<'de>,
+    {
+        crate::from_value_with(&self.0, config)
+    }
+}
+
+impl From<rmpv::Value> for LazyValue<'_> {
+    fn from(value: rmpv::Value) -> Self {
+        LazyValue(Cow::Owned(value))
+    }
+}
+
+impl<'a> From<&'a rmpv::Value> for LazyValue<'a> {
+    fn from(value: &'a rmpv::Value) -> Self {
+        LazyValue(Cow::Borrowed(value))
+    }
+}
+
+impl Serialize for LazyValue<'_> {
+    fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
+    where
+        S: Serializer,
+    {
+        self.0.serialize(serializer)
+    }
+}
+
+impl<'de> Deserialize<'de> for LazyValue<'_> {
+    fn deserialize<D>(deserializer: D) -> Result<Self, D::Error>
+    where
+        D: Deserializer<'de>,
+    {
+        struct LazyVisitor;
+
+        impl<'de> Visitor<'de> for LazyVisitor {
+            type Value = rmpv::Value;
+
+            fn expecting(&self, formatter: &mut fmt::Formatter) -> fmt::Result {
+                formatter.write_str("any MessagePack value")
+            }
+
+            fn visit_newtype_struct<D>(self, deserializer: D) -> Result<Self::Value, D::Error>
+            where
+                D: Deserializer<'de>,
+            {
+                rmpv::Value::deserialize(deserializer)
+            }
+        }
+
+        deserializer
+            .deserialize_newtype_struct(LAZY_VALUE_STRUCT_NAME, LazyVisitor)
+            .map(LazyValue::from)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use serde_derive::{Deserialize, Serialize};
+
+    use crate::{from_value, from_value_owned, to_value, ExtRegistry};
+
+    #[test]
+    fn test_lazy_value() {
+        #[derive(Serialize, Deserialize, Debug, PartialEq)]
+        struct Request<'a> {
+            method: String,
+            params: LazyValue<'a>,
+        }
+
+        let params = rmpv::Value::Array(vec![
+            rmpv::Value::F32(1.5),
+            rmpv::Value::Ext(7, vec![1]),
+            rmpv::Value::Map(vec![(rmpv::Value::from(1), rmpv::Value::Nil)]),
+        ]);
+        let req = Request {
+            method: "m".into(),
+            params: LazyValue::from(&params),
+        };
+        let val = to_value(&req).unwrap();
+        assert_eq!(Some(&params), val.as_map().map(|m| &m[1].1));
+        assert_eq!(req, from_value(&val).unwrap());
+        assert_eq!(req, from_value_owned(val.clone()).unwrap());
+
+        // Registered Ext types are only decoded on request.
+        let registry = ExtRegistry::new().register(
+            7,
+            |v: &u8| vec![*v],
+            |d| d.first().copied().ok_or("empty"),
+        );
+        let config = Config::new().with_ext_registry(registry);
+        let req: Request = crate::from_value_with(&val, &config).unwrap();
+        assert_eq!(&params, req.params.as_value());
+        let (f, n, _): (f32, u8, rmpv::Value) = req.params.deserialize_with(&config).unwrap();
+        assert_eq!((1.5, 1), (f, n));
+        req.params
+            .deserialize::<(f32, u8, rmpv::Value)>()
+            .expect_err("expected ext error");
+    }
+}
@@ -2,12 +2,62 @@
 //!
 //! This crate handles all Serde data model types and includes special support for MessagePack's
 //! Ext type through the [`MSGPACK_EXT_STRUCT_NAME`] type annotation.
+//!
+//! [`rmpv::Value`] fields nested in other types pass through unchanged, keeping
+//! Ext values, including those with tags in an [`ExtRegistry`], the F32 and
+//! F64 distinction, and non-string map keys. Strings that are not valid UTF-8
+//! come back as Binary, since rmpv serializes them as bytes. Borrowed
+//! [`rmpv::ValueRef`] fields are handled by the [`value_ref`] module.
+
+// Lets the derive macros refer to `::serde_rmpv` from within this crate.
+extern crate self as serde_rmpv;
 
+mod config;
 mod de;
 mod error;
+mod ext;
+mod lazy;
+pub mod registered;
 mod ser;
+pub mod timestamp;
+pub mod value_ref;
+
+pub use config::{
+    Config, DuplicateKeyPolicy, EnumRepr, Int128Fallback, IntegerPolicy, StructRepr, UnitStructRepr,
+};
+pub use de::Deserializer;
+pub use error::{Error, ErrorKind, Path, Segment, Unexpected};
+pub use ext::{ExtEncoded, ExtRegistry};
+pub use lazy::LazyValue;
+pub use registered::Registered;
+pub use ser::Serializer;
+pub use timestamp::Timestamp;
+
+#[cfg(feature = "derive")]
+pub use serde_rmpv_derive::MsgpackExt;
+
+/// Ext tags outside the `i8` range are rejected at compile time.
+///
+/// ```compile_fail
+/// #[derive(serde_rmpv::MsgpackExt)]
+/// #[msgpack_ext(tag = 128)]
+/// struct Ext(Vec<u8>);
+/// ```
+///
+/// ```compile_fail
+/// #[derive(serde_rmpv::MsgpackExt)]
+/// #[msgpack_ext(tag = -129)]
+/// struct Ext(Vec<u8>);
+/// ```
+#[cfg(all(doctest, feature = "derive"))]
+pub struct MsgpackExtTagRange;
 
-pub use error::Error;
+// Support for the code generated by the derive macros. Not public API.
+#[doc(hidden)]
+pub mod __private {
+    pub use crate::ext::{deserialize_ext_data, serialize_ext};
+    pub use serde;
+}
 
 /// Name of the Serde newtype struct to represent MessagePack's Ext type
 ///
@@ -35,6 +85,49 @@ where
     de::from_value(s)
 }
 
+/// Deserializes rmpv::Value into a target type, using the given configuration.
+///
+/// # Errors
+/// Returns an error if:
+/// - Value cannot be deserialized into target type
+/// - Value contains unsupported or invalid data for target type
+pub fn from_value_with<'a, T>(s: &'a rmpv::Value, config: &Config) -> Result<T, Error>
+where
+    T: serde::de::Deserialize<'a>,
+{
+    de::from_value_with(s, config)
+}
+
+/// Deserializes an owned rmpv::Value into a target type.
+///
+/// Strings, binaries and nested values are moved out of the input rather than
+/// copied.
+///
+/// # Errors
+/// Returns an error if:
+/// - Value cannot be deserialized into target type
+/// - Value contains unsupported or invalid data for target type
+pub fn from_value_owned<T>(s: rmpv::Value) -> Result<T, Error>
+where
+    T: serde::de::DeserializeOwned,
+{
+    de::from_value_owned(s)
+}
+
+/// Deserializes an owned rmpv::Value into a target type, using the given
+/// configuration.
+///
+/// # Errors
+/// Returns an error if:
+/// - Value cannot be deserialized into target type
+/// - Value contains unsupported or invalid data for target type
+pub fn from_value_owned_with<T>(s: rmpv::Value, config: &Config) -> Result<T, Error>
+where
+    T: serde::de::DeserializeOwned,
+{
+    de::from_value_owned_with(s, config)
+}
+
 /// Serializes a type into rmpv::Value.
 ///
 /// # Errors
@@ -48,6 +141,19 @@ where
     ser::to_value(value)
 }
 
+/// Serializes a type into rmpv::Value, using the given configuration.
+///
+/// # Errors
+/// Returns an error if:
+/// - Value cannot be serialized
+/// - Value contains unsupported types
+pub fn to_value_with<T>(value: &T, config: &Config) -> Result<rmpv::Value, Error>
+where
+    T: serde::ser::Serialize,
+{
+    ser::to_value_with(value, config)
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -67,4 +173,591 @@ mod tests {
         let f2 = from_value(&val).unwrap();
         assert_eq!(f, f2);
     }
+
+    #[test]
+    fn test_int128_idemp() {
+        #[derive(Serialize, Deserialize, Debug, PartialEq)]
+        struct S {
+            a: i128,
+            b: u128,
+        }
+
+        let small = S { a: -5, b: 5 };
+        let val = to_value(&small).unwrap();
+        assert_eq!(small, from_value(&val).unwrap());
+
+        let big = S {
+            a: i128::MIN,
+            b: u128::MAX,
+        };
+        to_value(&big).expect_err("expected range error");
+        for fallback in [Int128Fallback::Binary, Int128Fallback::Ext(3)] {
+            let config = Config::new().with_int128_fallback(fallback);
+            let val = to_value_with(&big, &config).unwrap();
+            assert_eq!(big, from_value_with(&val, &config).unwrap());
+        }
+    }
+
+    #[test]
+    fn test_enum_idemp() {
+        #[derive(Serialize, Deserialize, Debug, PartialEq)]
+        enum E {
+            Unit,
+            Newtype(u8),
+            Tuple(u8, String),
+            Empty(),
+            Struct { a: u8, b: Vec<u8> },
+        }
+
+        for e in [
+            E::Unit,
+            E::Newtype(1),
+            E::Tuple(1, "two".into()),
+            E::Empty(),
+            E::Struct {
+                a: 1,
+                b: vec![2, 3],
+            },
+        ] {
+            let val = to_value(&e).unwrap();
+            assert_eq!(e, from_value::<E>(&val).unwrap());
+            for repr in [
+                EnumRepr::NamedArray,
+                EnumRepr::ExternallyTagged,
+                EnumRepr::VariantIndex,
+                EnumRepr::IndexArray,
+            ] {
+                for struct_repr in [StructRepr::Map, StructRepr::Array, StructRepr::FieldIndex] {
+                    let config = Config::new()
+                        .with_enum_repr(repr)
+                        .with_struct_repr(struct_repr);
+                    let val = to_value_with(&e, &config).unwrap();
+                    assert_eq!(e, from_value::<E>(&val).unwrap());
+                }
+            }
+        }
+        from_value::<E>(&rmpv::Value::from(true)).expect_err("expected type error");
+    }
+
+    #[test]
+    fn test_struct_idemp() {
+        #[derive(Serialize, Deserialize, Debug, PartialEq)]
+        struct Inner {
+            a: u8,
+            #[serde(skip_serializing_if = "Option::is_none")]
+            b: Option<String>,
+        }
+
+        #[derive(Serialize, Deserialize, Debug, PartialEq)]
+        struct S {
+            inner: Inner,
+            items: Vec<Inner>,
+            last: bool,
+        }
+
+        let s = S {
+            inner: Inner { a: 1, b: None },
+            items: vec![Inner {
+                a: 2,
+                b: Some("b".into()),
+            }],
+            last: true,
+        };
+        for repr in [StructRepr::Map, StructRepr::Array, StructRepr::FieldIndex] {
+            let config = Config::new().with_struct_repr(repr);
+            let val = to_value_with(&s, &config).unwrap();
+            assert_eq!(s, from_value(&val).unwrap());
+            assert_eq!(s, from_value_owned(val).unwrap());
+        }
+
+        let val = to_value_with(&s, &Config::new().with_struct_repr(StructRepr::Array)).unwrap();
+        assert_eq!(Some(3), val.as_array().map(Vec::len));
+
+        // Fields that are skipped on one side only shift the positions of
+        // later fields, which positional representations don't support.
+        #[derive(Serialize, Deserialize, Debug, PartialEq)]
+        struct Shifted {
+            #[serde(skip)]
+            hidden: u8,
+            #[serde(skip_serializing, default)]
+            a: u8,
+            b: String,
+        }
+
+        let s = Shifted {
+            hidden: 0,
+            a: 1,
+            b: "b".into(),
+        };
+        for repr in [StructRepr::Array, StructRepr::FieldIndex] {
+            let val = to_value_with(&s, &Config::new().with_struct_repr(repr)).unwrap();
+            from_value::<Shifted>(&val).expect_err("expected shifted field error");
+        }
+        let val = to_value(&s).unwrap();
+        assert_eq!(Shifted { a: 0, ..s }, from_value(&val).unwrap());
+
+        // The nil written in place of a skipped field only reads back into
+        // fields that accept it.
+        #[derive(Serialize, Deserialize, Debug, PartialEq)]
+        struct Defaulted {
+            a: u8,
+            #[serde(default, skip_serializing_if = "Vec::is_empty")]
+            v: Vec<u8>,
+            c: u8,
+        }
+
+        let s = Defaulted {
+            a: 1,
+            v: vec![],
+            c: 2,
+        };
+        let val = to_value_with(&s, &Config::new().with_struct_repr(StructRepr::Array)).unwrap();
+        assert_eq!(
+            rmpv::Value::Array(vec![1.into(), rmpv::Value::Nil, 2.into()]),
+            val
+        );
+        let err = from_value::<Defaulted>(&val).unwrap_err();
+        assert!(matches!(err.kind(), ErrorKind::InvalidType { .. }));
+        assert_eq!("[1]", err.path().unwrap().to_string());
+        assert_eq!(s, from_value(&to_value(&s).unwrap()).unwrap());
+    }
+
+    #[test]
+    fn test_human_readable_idemp() {
+        use std::net::{IpAddr, SocketAddr};
+
+        let addrs: (IpAddr, SocketAddr) =
+            ("::1".parse().unwrap(), "127.0.0.1:8080".parse().unwrap());
+        for human_readable in [false, true] {
+            let config = Config::new().with_human_readable(human_readable);
+            let val = to_value_with(&addrs, &config).unwrap();
+            assert_eq!(addrs, from_value_with(&val, &config).unwrap());
+            assert_eq!(addrs, from_value_owned_with(val, &config).unwrap());
+        }
+    }
+
+    // Checks that `v` maps to the same value as rmp-serde's compact and named
+    // encodings, and that each side reads what the other writes.
+    fn assert_rmp_serde_parity<T>(v: &T)
+    where
+        T: serde::Serialize + serde::de::DeserializeOwned + PartialEq + std::fmt::Debug,
+    {
+        let compact = Config::rmp_serde_compat();
+        let named = Config::rmp_serde_compat().with_struct_repr(StructRepr::Map);
+        for (bytes, config) in [
+            (rmp_serde::to_vec(v).unwrap(), compact),
+            (rmp_serde::to_vec_named(v).unwrap(), named),
+        ] {
+            let expected = rmpv::decode::read_value(&mut bytes.as_slice()).unwrap();
+            let val = to_value_with(v, &config).unwrap();
+            assert_eq!(expected, val, "{:?}", v);
+            assert_eq!(v, &from_value::<T>(&expected).unwrap());
+
+            let mut ours = Vec::new();
+            rmpv::encode::write_value(&mut ours, &val).unwrap();
+            assert_eq!(v, &rmp_serde::from_slice::<T>(&ours).unwrap());
+        }
+    }
+
+    #[test]
+    fn test_rmp_serde_parity() {
+        #[derive(Serialize, Deserialize, Debug, PartialEq)]
+        struct Unit;
+
+        #[derive(Serialize, Deserialize, Debug, PartialEq)]
+        struct Newtype(u32);
+
+        #[derive(Serialize, Deserialize, Debug, PartialEq)]
+        struct Tuple(i8, String);
+
+        #[derive(Serialize, Deserialize, Debug, PartialEq)]
+        enum E {
+            Unit,
+            Newtype(u8),
+            Tuple(u8, String),
+            Empty(),
+            Struct {
+                a: u8,
+                #[serde(skip_serializing_if = "Option::is_none", default)]
+                b: Option<String>,
+            },
+        }
+
+        #[serde_as]
+        #[derive(Serialize, Deserialize, Debug, PartialEq)]
+        struct S {
+            unit: Unit,
+            newtype: Newtype,
+            tuple: Tuple,
+            enums: Vec<E>,
+            option: Option<Box<S>>,
+            #[serde_as(as = "Bytes")]
+            bytes: Vec<u8>,
+            plain_bytes: Vec<u8>,
+            map: std::collections::BTreeMap<u8, Vec<String>>,
+            floats: (f32, f64),
+            wide: (i128, u128),
+            c: char,
+            time: Timestamp,
+            #[serde(skip_serializing_if = "Option::is_none", default)]
+            note: Option<String>,
+        }
+
+        let leaf = S {
+            unit: Unit,
+            newtype: Newtype(7),
+            tuple: Tuple(-1, "t".into()),
+            enums: vec![],
+            option: None,
+            bytes: vec![],
+            plain_bytes: vec![1, 2],
+            map: Default::default(),
+            floats: (0.5, -0.25),
+            wide: (-1, u128::MAX),
+            c: 'é',
+            time: Timestamp::new(1, 2).unwrap(),
+            note: None,
+        };
+        let s = S {
+            enums: vec![
+                E::Unit,
+                E::Newtype(1),
+                E::Tuple(2, "two".into()),
+                E::Empty(),
+                E::Struct {
+                    a: 3,
+                    b: Some("b".into()),
+                },
+                E::Struct { a: 4, b: None },
+            ],
+            option: Some(Box::new(leaf)),
+            bytes: vec![0, 255],
+            map: [(1, vec!["a".into()]), (2, vec![])].into_iter().collect(),
+            wide: (i128::MIN, 5),
+            note: Some("n".into()),
+            ..S {
+                unit: Unit,
+                newtype: Newtype(0),
+                tuple: Tuple(0, String::new()),
+                enums: vec![],
+                option: None,
+                bytes: vec![],
+                plain_bytes: vec![],
+                map: Default::default(),
+                floats: (f32::MAX, f64::MIN_POSITIVE),
+                wide: (0, 0),
+                c: 'c',
+                time: Timestamp::new(-1, 999_999_999).unwrap(),
+                note: None,
+            }
+        };
+        assert_rmp_serde_parity(&s);
+        assert_rmp_serde_parity(&E::Unit);
+        assert_rmp_serde_parity(&Some(Unit));
+        assert_rmp_serde_parity(&Vec::<Option<u8>>::from([None, Some(1)]));
+
+        // A skipped field in the middle moves later fields up in the compact
+        // encoding, so neither side can read it back, but the values agree.
+        #[derive(Serialize)]
+        struct Sparse {
+            a: u8,
+            #[serde(skip_serializing_if = "Option::is_none")]
+            b: Option<u8>,
+            c: u8,
+        }
+
+        let sparse = Sparse {
+            a: 1,
+            b: None,
+            c: 3,
+        };
+        for (bytes, config) in [
+            (rmp_serde::to_vec(&sparse), Config::rmp_serde_compat()),
+            (
+                rmp_serde::to_vec_named(&sparse),
+                Config::rmp_serde_compat().with_struct_repr(StructRepr::Map),
+            ),
+        ] {
+            let expected = rmpv::decode::read_value(&mut bytes.unwrap().as_slice()).unwrap();
+            assert_eq!(expected, to_value_with(&sparse, &config).unwrap());
+        }
+    }
+
+    #[test]
+    fn test_ext_registry_idemp() {
+        #[derive(Serialize, Deserialize, Debug, PartialEq)]
+        struct Point {
+            x: u8,
+            y: u8,
+        }
+
+        #[derive(Serialize, Deserialize, Debug, PartialEq)]
+        struct Decimal(i64, u32);
+
+        // Holds data that is only visible to `encode`.
+        #[derive(Serialize, Deserialize, Debug, PartialEq)]
+        struct Secret {
+            #[serde(skip)]
+            key: u8,
+        }
+
+        #[derive(Serialize, Deserialize, Debug, PartialEq)]
+        struct S {
+            #[serde(with = "crate::registered")]
+            p: Point,
+            ps: Vec<Registered<Point>>,
+            d: Option<Registered<Decimal>>,
+            plain: Point,
+            raw: u8,
+        }
+
+        let registry = ExtRegistry::new()
+            .register(
+                5,
+                |p: &Point| vec![p.x, p.y],
+                |data: &[u8]| match data {
+                    [x, y] => Ok(Point { x: *x, y: *y }),
+                    _ => Err("expected two bytes"),
+                },
+            )
+            .register(
+                7,
+                |d: &Decimal| [d.0.to_be_bytes(), u64::from(d.1).to_be_bytes()].concat(),
+                |data: &[u8]| -> Result<Decimal, String> {
+                    let (m, s) = data.split_at_checked(8).ok_or("short decimal")?;
+                    Ok(Decimal(
+                        i64::from_be_bytes(m.try_into().map_err(|_| "bad mantissa")?),
+                        u64::from_be_bytes(s.try_into().map_err(|_| "bad scale")?) as u32,
+                    ))
+                },
+            )
+            .register(
+                9,
+                |s: &Secret| vec![s.key],
+                |data: &[u8]| match data {
+                    [key] => Ok(Secret { key: *key }),
+                    _ => Err("expected one byte"),
+                },
+            );
+        let config = Config::new().with_ext_registry(registry);
+
+        let s = S {
+            p: Point { x: 1, y: 2 },
+            ps: vec![Registered(Point { x: 3, y: 4 })],
+            d: Some(Registered(Decimal(-15, 1))),
+            plain: Point { x: 7, y: 8 },
+            raw: 9,
+        };
+        let val = to_value_with(&s, &config).unwrap();
+        assert_eq!(val["p"], rmpv::Value::Ext(5, vec![1, 2]));
+        assert_eq!(
+            val["ps"],
+            rmpv::Value::Array(vec![rmpv::Value::Ext(5, vec![3, 4])])
+        );
+        assert!(matches!(&val["d"], rmpv::Value::Ext(7, data) if data.len() == 16));
+        assert_eq!(val["plain"], to_value(&s.plain).unwrap());
+        assert_eq!(val["raw"], rmpv::Value::from(9));
+        assert_eq!(s, from_value_with(&val, &config).unwrap());
+        assert_eq!(
+            to_value_with(&Registered(Point { x: 5, y: 6 }), &config).unwrap(),
+            rmpv::Value::Ext(5, vec![5, 6])
+        );
+        assert_eq!(
+            to_value_with(&Registered(Secret { key: 3 }), &config).unwrap(),
+            rmpv::Value::Ext(9, vec![3])
+        );
+
+        // Without the registry, marked values are written as usual.
+        assert_eq!(
+            to_value(&Registered(Point { x: 5, y: 6 })).unwrap(),
+            to_value(&Point { x: 5, y: 6 }).unwrap()
+        );
+
+        // Without the registry, the Ext values are left alone.
+        from_value::<S>(&val).expect_err("expected type error");
+
+        let bad = rmpv::Value::Array(vec![rmpv::Value::Ext(5, vec![1])]);
+        let err = from_value_with::<Vec<Point>>(&bad, &config).unwrap_err();
+        assert_eq!("expected two bytes at [0]", err.to_string());
+
+        // Targets that accept any value see registered Ext types unchanged.
+        #[derive(Deserialize, Debug, PartialEq)]
+        struct Raw<'a> {
+            v: rmpv::Value,
+            o: Option<rmpv::Value>,
+            lazy: Option<LazyValue<'a>>,
+        }
+
+        let ext = rmpv::Value::Ext(5, vec![1, 2]);
+        let val = rmpv::Value::Map(vec![
+            ("v".into(), ext.clone()),
+            ("o".into(), ext.clone()),
+            ("lazy".into(), ext.clone()),
+        ]);
+        let raw: Raw = from_value_with(&val, &config).unwrap();
+        assert_eq!(ext, raw.v);
+        assert_eq!(Some(&ext), raw.o.as_ref());
+        assert_eq!(Some(&ext), raw.lazy.as_ref().map(LazyValue::as_value));
+        from_value_with::<serde::de::IgnoredAny>(&bad, &config).unwrap();
+    }
+
+    #[test]
+    fn test_derive_ext_idemp() {
+        use serde_rmpv_derive::MsgpackExt;
+
+        #[derive(MsgpackExt, Debug, PartialEq)]
+        #[msgpack_ext(tag = 12)]
+        struct Hash([u8; 4]);
+
+        #[derive(MsgpackExt, Debug, PartialEq)]
+        #[msgpack_ext(tag = -128)]
+        struct Blob {
+            data: Vec<u8>,
+        }
+
+        #[derive(MsgpackExt, Debug, PartialEq)]
+        #[msgpack_ext(tag = 127, encode = "Point::encode", decode = "Point::decode")]
+        struct Point {
+            x: u8,
+            y: u8,
+        }
+
+        impl Point {
+            fn encode(&self) -> [u8; 2] {
+                [self.x, self.y]
+            }
+
+            fn decode(data: &[u8]) -> Result<Self, &'static str> {
+                match data {
+                    [x, y] => Ok(Point { x: *x, y: *y }),
+                    _ => Err("expected two bytes"),
+                }
+            }
+        }
+
+        #[derive(Serialize, Deserialize, Debug, PartialEq)]
+        struct S {
+            hash: Hash,
+            blob: Blob,
+            point: Point,
+        }
+
+        let s = S {
+            hash: Hash([1, 2, 3, 4]),
+            blob: Blob { data: vec![5] },
+            point: Point { x: 6, y: 7 },
+        };
+        let val = to_value(&s).unwrap();
+        assert_eq!(val["hash"], rmpv::Value::Ext(12, vec![1, 2, 3, 4]));
+        assert_eq!(val["blob"], rmpv::Value::Ext(-128, vec![5]));
+        assert_eq!(val["point"], rmpv::Value::Ext(127, vec![6, 7]));
+        assert_eq!(s, from_value(&val).unwrap());
+
+        from_value::<Hash>(&rmpv::Value::Ext(12, vec![1])).expect_err("expected length error");
+        from_value::<Hash>(&rmpv::Value::Ext(13, vec![1, 2, 3, 4]))
+            .expect_err("expected tag error");
+        let err = from_value::<Point>(&rmpv::Value::Ext(127, vec![])).unwrap_err();
+        assert_eq!("expected two bytes", err.to_string());
+    }
+
+    #[test]
+    fn test_compose() {
+        use serde::de::{Deserialize as _, DeserializeSeed, IntoDeserializer};
+
+        #[derive(Serialize, Deserialize, Debug, PartialEq)]
+        struct S {
+            a: u8,
+            b: Vec<String>,
+        }
+
+        let s = S {
+            a: 1,
+            b: vec!["x".into()],
+        };
+        let mut serializer = Serializer::new();
+        serde_path_to_error::serialize(&s, &mut serializer).unwrap();
+        let val = serializer.into_value();
+        assert_eq!(val, to_value(&s).unwrap());
+
+        let seed = std::marker::PhantomData::<S>;
+        assert_eq!(s, seed.deserialize(Deserializer::new(&val)).unwrap());
+        assert_eq!(s, S::deserialize((&val).into_deserializer()).unwrap());
+        assert_eq!(s, S::deserialize(val.clone().into_deserializer()).unwrap());
+
+        let config = Config::new().with_enum_repr(EnumRepr::IndexArray);
+        let de = Deserializer::from_owned(val).with_config(config);
+        assert_eq!(s, serde_path_to_error::deserialize(de).unwrap());
+
+        let bad = rmpv::Value::Map(vec![
+            ("a".into(), 1.into()),
+            ("b".into(), rmpv::Value::Array(vec![2.into()])),
+        ]);
+        let err = serde_path_to_error::deserialize::<_, S>(Deserializer::new(&bad)).unwrap_err();
+        assert_eq!("b[0]", err.path().to_string());
+    }
+
+    #[test]
+    fn test_owned_idemp() {
+        #[serde_as]
+        #[derive(Serialize, Deserialize, Debug, PartialEq)]
+        struct S {
+            name: String,
+            #[serde_as(as = "Bytes")]
+            data: Vec<u8>,
+            nested: Vec<Vec<String>>,
+            map: std::collections::BTreeMap<String, u8>,
+            e: Option<E>,
+        }
+
+        #[derive(Serialize, Deserialize, Debug, PartialEq)]
+        enum E {
+            Tuple(u8, String),
+        }
+
+        let s = S {
+            name: "name".into(),
+            data: vec![1, 2, 3],
+            nested: vec![vec!["a".into()], vec![]],
+            map: [("k".to_string(), 1)].into_iter().collect(),
+            e: Some(E::Tuple(1, "two".into())),
+        };
+        let val = to_value(&s).unwrap();
+        assert_eq!(s, from_value(&val).unwrap());
+        assert_eq!(s, from_value_owned(val).unwrap());
+
+        let config = Config::new().with_enum_repr(EnumRepr::ExternallyTagged);
+        let val = to_value_with(&s, &config).unwrap();
+        assert_eq!(s, from_value_owned_with(val, &config).unwrap());
+    }
+
+    #[test]
+    fn test_value_idemp() {
+        #[derive(Serialize, Deserialize, Debug, PartialEq)]
+        struct Msg {
+            id: u32,
+            payload: rmpv::Value,
+        }
+
+        for payload in [
+            rmpv::Value::Nil,
+            rmpv::Value::from(u64::MAX),
+            rmpv::Value::from(i64::MIN),
+            rmpv::Value::F32(1.5),
+            rmpv::Value::F64(1.5),
+            rmpv::Value::from("s"),
+            rmpv::Value::Binary(vec![1, 2]),
+            rmpv::Value::Ext(5, vec![]),
+            rmpv::Value::Array(vec![rmpv::Value::Nil, rmpv::Value::Ext(-1, vec![1])]),
+            rmpv::Value::Map(vec![
+                (rmpv::Value::from(1), rmpv::Value::F32(2.0)),
+                (rmpv::Value::Nil, rmpv::Value::Array(vec![])),
+            ]),
+        ] {
+            let msg = Msg { id: 1, payload };
+            let val = to_value(&msg).unwrap();
+            assert_eq!(Some(&msg.payload), val.as_map().map(|m| &m[1].1));
+            assert_eq!(msg, from_value(&val).unwrap());
+            assert_eq!(msg, from_value_owned(val).unwrap());
+        }
+    }
 }
@@ -0,0 +1,136 @@
+//! Writing values of types in an [`ExtRegistry`] as Ext values.
+//!
+//! The serializer writes a registered type as an Ext value where it is marked,
+//! either by wrapping the value in [`Registered`] or with
+//! `#[serde(with = "serde_rmpv::registered")]` on a field. The marker lets the
+//! registry find the type by its `TypeId` and hand the original value to the
+//! registered `encode` function. Elsewhere, and with serializers other than
+//! this crate's, the value is written as usual.
+//!
+//! ```rust
+//! use serde_derive::{Deserialize, Serialize};
+//! use serde_rmpv::{Config, ExtRegistry, Registered};
+//!
+//! #[derive(Serialize, Deserialize, Debug, PartialEq)]
+//! struct Point(u8, u8);
+//!
+//! #[derive(Serialize, Deserialize, Debug, PartialEq)]
+//! struct Shape {
+//!     #[serde(with = "serde_rmpv::registered")]
+//!     origin: Point,
+//!     points: Vec<Registered<Point>>,
+//! }
+//!
+//! let registry = ExtRegistry::new().register(
+//!     5,
+//!     |p: &Point| vec![p.0, p.1],
+//!     |data: &[u8]| match data {
+//!         [x, y] => Ok(Point(*x, *y)),
+//!         _ => Err("expected 2 bytes"),
+//!     },
+//! );
+//! let config = Config::new().with_ext_registry(registry);
+//!
+//! let shape = Shape {
+//!     origin: Point(0, 0),
+//!     points: vec![Registered(Point(1, 2))],
+//! };
+//! let value = serde_rmpv::to_value_with(&shape, &config).unwrap();
+//! assert_eq!(rmpv::Value::Ext(5, vec![0, 0]), value["origin"]);
+//! assert_eq!(shape, serde_rmpv::from_value_with(&value, &config).unwrap());
+//! ```
+//!
+//! Deserializing needs no marker, as Ext values with a registered tag are
+//! decoded wherever the target asks for a specific type.
+
+use std::cell::RefCell;
+
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
+
+use crate::ext::{self, ExtRegistry};
+
+/// Name of the newtype struct through which a marked value asks the
+/// serializer for its `ExtRegistry`.
+pub(crate) const REGISTERED_STRUCT_NAME: &str = "$serde_rmpv::private::Registered";
+
+thread_local! {
+    // The registry of the serializer writing a marked value, handed from the
+    // serializer to the `Lookup` it is serializing.
+    static REGISTRY: RefCell<Option<ExtRegistry>> = const { RefCell::new(None) };
+}
+
+// Run `f`, which serializes a `Lookup`, with `registry` handed to it.
+pub(crate) fn with_registry<R>(registry: &ExtRegistry, f: impl FnOnce() -> R) -> R {
+    REGISTRY.with(|r| r.replace(Some(registry.clone())));
+    let result = f();
+    REGISTRY.with(|r| r.take());
+    result
+}
+
+/// Serializes `value` as an Ext value if its type is in the serializer's
+/// [`ExtRegistry`], or as usual otherwise.
+pub fn serialize<T, S>(value: &T, serializer: S) -> Result<S::Ok, S::Error>
+where
+    T: Serialize + 'static,
+    S: Serializer,
+{
+    serializer.serialize_newtype_struct(REGISTERED_STRUCT_NAME, &Lookup(value))
+}
+
+/// Deserializes a value as usual; registered Ext tags are decoded by the
+/// deserializer.
+pub fn deserialize<'de, T, D>(deserializer: D) -> Result<T, D::Error>
+where
+    T: Deserialize<'de>,
+    D: Deserializer<'de>,
+{
+    T::deserialize(deserializer)
+}
+
+// A marked value, which takes the registry as soon as it is serialized so that
+// values nested in it don't see it.
+struct Lookup<'a, T>(&'a T);
+
+impl<T: Serialize + 'static> Serialize for Lookup<'_, T> {
+    fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
+    where
+        S: Serializer,
+    {
+        let registry = REGISTRY.with(|r| r.take());
+        match registry.and_then(|r| r.encode(self.0)) {
+            Some((tag, data)) => ext::serialize_ext(serializer, tag, &data),
+            None => self.0.serialize(serializer),
+        }
+    }
+}
+
+/// A value written as an Ext value if its type is in the serializer's
+/// [`ExtRegistry`], for use where `#[serde(with = "serde_rmpv::registered")]`
+/// doesn't reach, such as the elements of a collection.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct Registered<T>(pub T);
+
+impl<T> Registered<T> {
+    /// Consumes the wrapper, returning the value.
+    pub fn into_inner(self) -> T {
+        self.0
+    }
+}
+
+impl<T: Serialize + 'static> Serialize for Registered<T> {
+    fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
+    where
+        S: Serializer,
+    {
+        serialize(&self.0, serializer)
+    }
+}
+
+impl<'de, T: Deserialize<'de>> Deserialize<'de> for Registered<T> {
+    fn deserialize<D>(deserializer: D) -> Result<Self, D::Error>
+    where
+        D: Deserializer<'de>,
+    {
+        T::deserialize(deserializer).map(Registered)
+    }
+}
@@ -1,43 +1,192 @@
+use std::borrow::Cow;
+
 use serde::{ser, Serialize};
 
-use crate::{error::*, MSGPACK_EXT_STRUCT_NAME};
+use crate::{
+    config::{Config, EnumRepr, Int128Fallback, StructRepr, UnitStructRepr},
+    error::*,
+    registered::{self, REGISTERED_STRUCT_NAME},
+    MSGPACK_EXT_STRUCT_NAME,
+};
 
 pub fn to_value<T>(value: &T) -> Result<rmpv::Value, Error>
 where
     T: Serialize,
 {
-    let mut serializer = Serializer {
-        output: rmpv::Value::Nil,
-    };
-    value.serialize(&mut serializer)?;
-    Ok(serializer.output)
+    to_value_with(value, &Config::default())
 }
 
-struct Serializer {
+pub fn to_value_with<T>(value: &T, config: &Config) -> Result<rmpv::Value, Error>
+where
+    T: Serialize,
+{
+    Serializer::nested(Cow::Borrowed(config)).serialize_child(value)
+}
+
+/// A `serde::Serializer` producing an `rmpv::Value`.
+///
+/// The serializer trait is implemented for `&mut Serializer`; the result is
+/// retrieved with [`Serializer::into_value`]. The serializers for nested
+/// values borrow the configuration of their parent.
+///
+/// # Example
+/// ```rust
+/// use serde::Serialize;
+/// use serde_rmpv::Serializer;
+///
+/// let mut serializer = Serializer::new();
+/// "hello".serialize(&mut serializer).unwrap();
+/// assert_eq!(rmpv::Value::from("hello"), serializer.into_value());
+/// ```
+#[derive(Debug)]
+pub struct Serializer<'c> {
     output: rmpv::Value,
+    config: Cow<'c, Config>,
+    // The index of the next field of the struct being serialized.
+    field_index: u32,
+}
+
+impl Default for Serializer<'static> {
+    fn default() -> Self {
+        Self::new()
+    }
 }
 
-impl Serializer {
+impl Serializer<'static> {
+    /// Creates a serializer with the default configuration.
+    pub fn new() -> Self {
+        Serializer::nested(Cow::Owned(Config::default()))
+    }
+}
+
+impl<'c> Serializer<'c> {
+    /// Sets the configuration used while serializing.
+    pub fn with_config(mut self, config: Config) -> Self {
+        self.config = Cow::Owned(config);
+        self
+    }
+
+    fn nested(config: Cow<'c, Config>) -> Self {
+        Serializer {
+            output: rmpv::Value::Nil,
+            config,
+            field_index: 0,
+        }
+    }
+
+    /// Consumes the serializer, returning the serialized value.
+    pub fn into_value(self) -> rmpv::Value {
+        self.output
+    }
+
+    // A fresh serializer for a nested value, sharing our configuration.
+    fn child(&self) -> Serializer<'_> {
+        Serializer::nested(Cow::Borrowed(&self.config))
+    }
+
+    // Serialize a nested value.
+    fn serialize_child<T>(&self, value: &T) -> RResult<rmpv::Value>
+    where
+        T: ?Sized + Serialize,
+    {
+        let mut serializer = self.child();
+        value.serialize(&mut serializer)?;
+        Ok(serializer.output)
+    }
+
+    // The identifier a variant is written with under the configured enum
+    // representation.
+    fn variant_id(&self, variant_index: u32, variant: &'static str) -> rmpv::Value {
+        match self.config.enum_repr {
+            EnumRepr::NamedArray | EnumRepr::ExternallyTagged => rmpv::Value::from(variant),
+            EnumRepr::VariantIndex | EnumRepr::IndexArray => rmpv::Value::from(variant_index),
+        }
+    }
+
+    // Wrap the data of a non-unit variant in the configured enum
+    // representation: appended to an array for the array representations,
+    // or as the value of a single-entry map for the tagged ones.
+    fn wrap_variant(
+        &self,
+        name: &'static str,
+        variant_index: u32,
+        variant: &'static str,
+        data: rmpv::Value,
+    ) -> rmpv::Value {
+        let id = self.variant_id(variant_index, variant);
+        match self.config.enum_repr {
+            EnumRepr::NamedArray => {
+                rmpv::Value::Array(vec![rmpv::Value::String(name.into()), id, data])
+            }
+            EnumRepr::IndexArray => rmpv::Value::Array(vec![id, data]),
+            EnumRepr::ExternallyTagged | EnumRepr::VariantIndex => {
+                rmpv::Value::Map(vec![(id, data)])
+            }
+        }
+    }
+
+    // An empty container for the fields of a struct or struct variant,
+    // according to the configured `StructRepr`.
+    fn struct_fields(&mut self) -> rmpv::Value {
+        self.field_index = 0;
+        match self.config.struct_repr {
+            StructRepr::Map | StructRepr::FieldIndex => rmpv::Value::Map(Vec::new()),
+            StructRepr::Array => rmpv::Value::Array(Vec::new()),
+        }
+    }
+
+    // The map key of the next field, which is counted whether or not it is
+    // skipped.
+    fn next_field_key(&mut self, name: &'static str) -> rmpv::Value {
+        let index = self.field_index;
+        self.field_index += 1;
+        match self.config.struct_repr {
+            StructRepr::FieldIndex => rmpv::Value::from(index),
+            StructRepr::Map | StructRepr::Array => rmpv::Value::String(name.into()),
+        }
+    }
+
+    // The fields of the struct variant being serialized, held in the data of
+    // the wrapped variant.
+    fn variant_fields(&mut self) -> Option<&mut rmpv::Value> {
+        match &mut self.output {
+            rmpv::Value::Map(ref mut map) => map.last_mut().map(|(_, v)| v),
+            rmpv::Value::Array(ref mut vec) => vec.last_mut(),
+            _ => None,
+        }
+    }
+
+    // Write the big-endian bytes of a 128-bit integer that doesn't fit in a
+    // MessagePack integer, according to the configured `Int128Fallback`.
+    fn serialize_int128(&mut self, v: impl std::fmt::Display, bytes: [u8; 16]) -> RResult<()> {
+        self.output = match self.config.int128_fallback {
+            Int128Fallback::Error => return Err(ErrorKind::Int128OutOfRange(v.to_string()).into()),
+            Int128Fallback::Binary | Int128Fallback::AlwaysBinary => {
+                rmpv::Value::Binary(bytes.to_vec())
+            }
+            Int128Fallback::Ext(tag) => rmpv::Value::Ext(tag, bytes.to_vec()),
+        };
+        Ok(())
+    }
+
     // Serialize a single element of the sequence.
     fn serialize_seq_element<T>(&mut self, value: &T) -> RResult<()>
     where
         T: ?Sized + Serialize,
     {
+        let value = self.serialize_child(value)?;
+
         match &mut self.output {
             rmpv::Value::Array(ref mut vec) => {
-                let mut serializer = Serializer {
-                    output: rmpv::Value::Nil,
-                };
-                value.serialize(&mut serializer)?;
-                vec.push(serializer.output);
+                vec.push(value);
                 Ok(())
             }
-            _ => Err(Error::TypeError("expected array".to_string())),
+            _ => Err(ser::Error::custom("expected array")),
         }
     }
 }
 
-impl ser::Serializer for &mut Serializer {
+impl ser::Serializer for &mut Serializer<'_> {
     type Ok = ();
 
     // The error type when some error occurs during serialization.
@@ -55,6 +204,11 @@ impl ser::Serializer for &mut Serializer {
     type SerializeStruct = Self;
     type SerializeStructVariant = Self;
 
+    // Compact by default, see `Config::with_human_readable`.
+    fn is_human_readable(&self) -> bool {
+        self.config.human_readable
+    }
+
     fn serialize_bool(self, v: bool) -> RResult<()> {
         self.output = rmpv::Value::Boolean(v);
         Ok(())
@@ -77,6 +231,18 @@ impl ser::Serializer for &mut Serializer {
         Ok(())
     }
 
+    fn serialize_i128(self, v: i128) -> RResult<()> {
+        if self.config.int128_fallback == Int128Fallback::AlwaysBinary {
+            self.serialize_int128(v, v.to_be_bytes())
+        } else if let Ok(v) = i64::try_from(v) {
+            self.serialize_i64(v)
+        } else if let Ok(v) = u64::try_from(v) {
+            self.serialize_u64(v)
+        } else {
+            self.serialize_int128(v, v.to_be_bytes())
+        }
+    }
+
     fn serialize_u8(self, v: u8) -> RResult<()> {
         self.serialize_u64(u64::from(v))
     }
@@ -94,6 +260,15 @@ impl ser::Serializer for &mut Serializer {
         Ok(())
     }
 
+    fn serialize_u128(self, v: u128) -> RResult<()> {
+        match u64::try_from(v) {
+            Ok(v) if self.config.int128_fallback != Int128Fallback::AlwaysBinary => {
+                self.serialize_u64(v)
+            }
+            _ => self.serialize_int128(v, v.to_be_bytes()),
+        }
+    }
+
     fn serialize_f32(self, v: f32) -> RResult<()> {
         self.output = rmpv::Value::F32(v);
         Ok(())
@@ -125,7 +300,8 @@ impl ser::Serializer for &mut Serializer {
     where
         T: ?Sized + Serialize,
     {
-        value.serialize(self)
+        self.output = self.serialize_child(value)?;
+        Ok(())
     }
 
     // In Serde, unit means an anonymous value containing no data. Map this to
@@ -138,7 +314,13 @@ impl ser::Serializer for &mut Serializer {
     // Unit struct means a named value containing no data. Again, since there is
     // no data, map this to msgpack as `nil`.
     fn serialize_unit_struct(self, _name: &'static str) -> RResult<()> {
-        self.serialize_unit()
+        match self.config.unit_struct_repr {
+            UnitStructRepr::Nil => self.serialize_unit(),
+            UnitStructRepr::EmptyArray => {
+                self.output = rmpv::Value::Array(Vec::new());
+                Ok(())
+            }
+        }
     }
 
     // When serializing a unit variant (or any other kind of variant), formats
@@ -148,10 +330,15 @@ impl ser::Serializer for &mut Serializer {
     fn serialize_unit_variant(
         self,
         _name: &'static str,
-        _variant_index: u32,
+        variant_index: u32,
         variant: &'static str,
     ) -> RResult<()> {
-        self.serialize_str(variant)
+        let id = self.variant_id(variant_index, variant);
+        self.output = match self.config.enum_repr {
+            EnumRepr::IndexArray => rmpv::Value::Array(vec![id]),
+            _ => id,
+        };
+        Ok(())
     }
 
     // As is done here, serializers are encouraged to treat newtype structs as
@@ -161,19 +348,30 @@ impl ser::Serializer for &mut Serializer {
         T: ?Sized + Serialize,
     {
         if name == MSGPACK_EXT_STRUCT_NAME {
-            let nv = to_value(&value)?;
+            // The value must be a `(tag, data)` pair with a tag in the i8
+            // range, and data given as binary or as an array of bytes.
+            // Anything else is an error rather than a panic, since ext
+            // structs may be built from untrusted data.
+            let nv = self.serialize_child(value)?;
             if let rmpv::Value::Array(vec) = nv {
-                if vec.len() == 2 {
-                    let id: i8 = vec[0].as_u64().unwrap().try_into().unwrap();
-                    if let rmpv::Value::Binary(data) = &vec[1] {
-                        self.output = rmpv::Value::Ext(id, data.clone());
+                if let Ok([tag, data]) = <[rmpv::Value; 2]>::try_from(vec) {
+                    let tag = tag.as_i64().and_then(|t| i8::try_from(t).ok());
+                    if let (Some(tag), Some(data)) = (tag, ext_data(data)) {
+                        self.output = rmpv::Value::Ext(tag, data);
                         return Ok(());
                     }
                 }
             }
-            Err(Error::TypeError("invalid ext struct".to_string()))
+            Err(ErrorKind::InvalidExtStruct.into())
+        } else if name == REGISTERED_STRUCT_NAME && !self.config.ext_registry.is_empty() {
+            // A value marked as registered, which looks itself up in our
+            // registry.
+            let registry = &self.config.ext_registry;
+            self.output = registered::with_registry(registry, || self.serialize_child(value))?;
+            Ok(())
         } else {
-            value.serialize(self)
+            self.output = self.serialize_child(value)?;
+            Ok(())
         }
     }
 
@@ -182,26 +380,19 @@ impl ser::Serializer for &mut Serializer {
     }
 
     // NewType variants are represented as Array<Vec[ENUM_NAME, VARIANT_NAME, DATA]>
+    // by default, see `EnumRepr` for the alternatives.
     fn serialize_newtype_variant<T>(
         self,
         name: &'static str,
-        _variant_index: u32,
+        variant_index: u32,
         variant: &'static str,
         value: &T,
     ) -> RResult<()>
     where
         T: ?Sized + Serialize,
     {
-        let mut serializer = Serializer {
-            output: rmpv::Value::Nil,
-        };
-        value.serialize(&mut serializer)?;
-
-        self.output = rmpv::Value::Array(vec![
-            rmpv::Value::String(name.into()),
-            rmpv::Value::String(variant.into()),
-            serializer.output,
-        ]);
+        let value = self.serialize_child(value)?;
+        self.output = self.wrap_variant(name, variant_index, variant, value);
         Ok(())
     }
 
@@ -228,18 +419,21 @@ impl ser::Serializer for &mut Serializer {
         self.serialize_seq(Some(len))
     }
 
-    // Tuple variants are represented as Array<Vec[ENUM_NAME, VARIANT_NAME, ... DATA ...]>.
+    // Tuple variants are represented as Array<Vec[ENUM_NAME, VARIANT_NAME, ... DATA ...]>
+    // by default. The tagged representations hold the data in an array instead.
     fn serialize_tuple_variant(
         self,
         name: &'static str,
-        _variant_index: u32,
+        variant_index: u32,
         variant: &'static str,
         _len: usize,
     ) -> RResult<Self::SerializeTupleVariant> {
-        self.output = rmpv::Value::Array(vec![
-            rmpv::Value::String(name.into()),
-            rmpv::Value::String(variant.into()),
-        ]);
+        self.output =
+            self.wrap_variant(name, variant_index, variant, rmpv::Value::Array(Vec::new()));
+        // Array representations have the data flattened into the variant array.
+        if let rmpv::Value::Array(vec) = &mut self.output {
+            vec.pop();
+        }
         Ok(self)
     }
 
@@ -248,30 +442,29 @@ impl ser::Serializer for &mut Serializer {
         Ok(self)
     }
 
-    // Structs look just like maps.
-    fn serialize_struct(self, _name: &'static str, len: usize) -> RResult<Self::SerializeStruct> {
-        self.serialize_map(Some(len))
+    // Structs are represented as `{ K: V, ... }` by default, see `StructRepr`
+    // for the alternatives.
+    fn serialize_struct(self, _name: &'static str, _len: usize) -> RResult<Self::SerializeStruct> {
+        self.output = self.struct_fields();
+        Ok(self)
     }
 
-    // Struct variants are represented as `[ ENUM_NAME, VARIANT_NAME: { K: V, ... } ]`.
-    // This is the externally tagged representation.
+    // Struct variants are represented as `[ ENUM_NAME, VARIANT_NAME, { K: V, ... } ]`
+    // by default, see `EnumRepr` and `StructRepr` for the alternatives.
     fn serialize_struct_variant(
         self,
         name: &'static str,
-        _variant_index: u32,
+        variant_index: u32,
         variant: &'static str,
         _len: usize,
     ) -> RResult<Self::SerializeStructVariant> {
-        self.output = rmpv::Value::Array(vec![
-            rmpv::Value::String(name.into()),
-            rmpv::Value::String(variant.into()),
-            rmpv::Value::Map(Vec::new()),
-        ]);
+        let fields = self.struct_fields();
+        self.output = self.wrap_variant(name, variant_index, variant, fields);
         Ok(self)
     }
 }
 
-impl ser::SerializeSeq for &mut Serializer {
+impl ser::SerializeSeq for &mut Serializer<'_> {
     type Ok = ();
     type Error = Error;
 
@@ -287,7 +480,7 @@ impl ser::SerializeSeq for &mut Serializer {
     }
 }
 
-impl ser::SerializeTuple for &mut Serializer {
+impl ser::SerializeTuple for &mut Serializer<'_> {
     type Ok = ();
     type Error = Error;
 
@@ -303,7 +496,7 @@ impl ser::SerializeTuple for &mut Serializer {
     }
 }
 
-impl ser::SerializeTupleStruct for &mut Serializer {
+impl ser::SerializeTupleStruct for &mut Serializer<'_> {
     type Ok = ();
     type Error = Error;
 
@@ -319,7 +512,7 @@ impl ser::SerializeTupleStruct for &mut Serializer {
     }
 }
 
-impl ser::SerializeTupleVariant for &mut Serializer {
+impl ser::SerializeTupleVariant for &mut Serializer<'_> {
     type Ok = ();
     type Error = Error;
 
@@ -327,7 +520,19 @@ impl ser::SerializeTupleVariant for &mut Serializer {
     where
         T: ?Sized + Serialize,
     {
-        self.serialize_seq_element(value)
+        let value = self.serialize_child(value)?;
+
+        let fields = match &mut self.output {
+            rmpv::Value::Map(ref mut map) => map.last_mut().map(|(_, v)| v),
+            array => Some(array),
+        };
+        match fields {
+            Some(rmpv::Value::Array(ref mut vec)) => {
+                vec.push(value);
+                Ok(())
+            }
+            _ => Err(ser::Error::custom("expected array")),
+        }
     }
 
     fn end(self) -> RResult<()> {
@@ -335,7 +540,7 @@ impl ser::SerializeTupleVariant for &mut Serializer {
     }
 }
 
-impl ser::SerializeMap for &mut Serializer {
+impl ser::SerializeMap for &mut Serializer<'_> {
     type Ok = ();
     type Error = Error;
 
@@ -343,16 +548,14 @@ impl ser::SerializeMap for &mut Serializer {
     where
         T: ?Sized + Serialize,
     {
+        let key = self.serialize_child(key)?;
+
         match &mut self.output {
             rmpv::Value::Map(ref mut vec) => {
-                let mut serializer = Serializer {
-                    output: rmpv::Value::Nil,
-                };
-                key.serialize(&mut serializer)?;
-                vec.push((serializer.output, rmpv::Value::Nil));
+                vec.push((key, rmpv::Value::Nil));
                 Ok(())
             }
-            _ => Err(Error::TypeError("expected map".to_string())),
+            _ => Err(ser::Error::custom("expected map")),
         }
     }
 
@@ -360,17 +563,15 @@ impl ser::SerializeMap for &mut Serializer {
     where
         T: ?Sized + Serialize,
     {
+        let value = self.serialize_child(value)?;
+
         match &mut self.output {
             rmpv::Value::Map(ref mut vec) => {
-                let mut serializer = Serializer {
-                    output: rmpv::Value::Nil,
-                };
-                value.serialize(&mut serializer)?;
                 let last = vec.len() - 1;
-                vec[last].1 = serializer.output;
+                vec[last].1 = value;
                 Ok(())
             }
-            _ => Err(Error::TypeError("expected map".to_string())),
+            _ => Err(ser::Error::custom("expected map")),
         }
     }
 
@@ -379,7 +580,7 @@ impl ser::SerializeMap for &mut Serializer {
     }
 }
 
-impl ser::SerializeStruct for &mut Serializer {
+impl ser::SerializeStruct for &mut Serializer<'_> {
     type Ok = ();
     type Error = Error;
 
@@ -387,23 +588,15 @@ impl ser::SerializeStruct for &mut Serializer {
     where
         T: ?Sized + Serialize,
     {
-        match &mut self.output {
-            rmpv::Value::Map(ref mut vec) => {
-                let mut keyser = Serializer {
-                    output: rmpv::Value::Nil,
-                };
-                key.serialize(&mut keyser)?;
-
-                let mut valser = Serializer {
-                    output: rmpv::Value::Nil,
-                };
-                value.serialize(&mut valser)?;
+        let value = self.serialize_child(value)?;
+        let key = self.next_field_key(key);
+        push_field(Some(&mut self.output), key, value)
+    }
 
-                vec.push((keyser.output, valser.output));
-                Ok(())
-            }
-            _ => Err(Error::TypeError("expected map".to_string())),
-        }
+    fn skip_field(&mut self, key: &'static str) -> RResult<()> {
+        self.next_field_key(key);
+        let omit = self.config.omit_skipped_fields;
+        skip_field(Some(&mut self.output), omit)
     }
 
     fn end(self) -> RResult<()> {
@@ -411,7 +604,7 @@ impl ser::SerializeStruct for &mut Serializer {
     }
 }
 
-impl ser::SerializeStructVariant for &mut Serializer {
+impl ser::SerializeStructVariant for &mut Serializer<'_> {
     type Ok = ();
     type Error = Error;
 
@@ -419,25 +612,15 @@ impl ser::SerializeStructVariant for &mut Serializer {
     where
         T: ?Sized + Serialize,
     {
-        match &mut self.output {
-            rmpv::Value::Array(ref mut vec) => {
-                let mut serializer = Serializer {
-                    output: rmpv::Value::Nil,
-                };
-                value.serialize(&mut serializer)?;
-
-                let last_off = vec.len() - 1;
-                let last = &mut vec[last_off];
-                match last {
-                    rmpv::Value::Map(ref mut map) => {
-                        map.push((rmpv::Value::String(key.into()), serializer.output));
-                    }
-                    _ => return Err(Error::TypeError("expected map".to_string())),
-                }
-                Ok(())
-            }
-            _ => Err(Error::TypeError("expected array".to_string())),
-        }
+        let value = self.serialize_child(value)?;
+        let key = self.next_field_key(key);
+        push_field(self.variant_fields(), key, value)
+    }
+
+    fn skip_field(&mut self, key: &'static str) -> RResult<()> {
+        self.next_field_key(key);
+        let omit = self.config.omit_skipped_fields;
+        skip_field(self.variant_fields(), omit)
     }
 
     fn end(self) -> RResult<()> {
@@ -445,6 +628,47 @@ impl ser::SerializeStructVariant for &mut Serializer {
     }
 }
 
+// Add a field to the fields of a struct or struct variant.
+fn push_field(
+    fields: Option<&mut rmpv::Value>,
+    key: rmpv::Value,
+    value: rmpv::Value,
+) -> RResult<()> {
+    match fields {
+        Some(rmpv::Value::Map(ref mut map)) => {
+            map.push((key, value));
+            Ok(())
+        }
+        Some(rmpv::Value::Array(ref mut vec)) => {
+            vec.push(value);
+            Ok(())
+        }
+        _ => Err(ser::Error::custom("expected map or array")),
+    }
+}
+
+// Skipped fields are left out of maps, but hold their place in arrays unless
+// `omit` is set.
+fn skip_field(fields: Option<&mut rmpv::Value>, omit: bool) -> RResult<()> {
+    match fields {
+        Some(rmpv::Value::Array(ref mut vec)) if !omit => vec.push(rmpv::Value::Nil),
+        _ => {}
+    }
+    Ok(())
+}
+
+// The data of an ext struct, from binary or an array of u8 integers.
+fn ext_data(data: rmpv::Value) -> Option<Vec<u8>> {
+    match data {
+        rmpv::Value::Binary(data) => Some(data),
+        rmpv::Value::Array(items) => items
+            .iter()
+            .map(|b| b.as_u64().and_then(|b| u8::try_from(b).ok()))
+            .collect(),
+        _ => None,
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -463,6 +687,233 @@ mod tests {
 
         let foo = Foo((42, vec![1, 2, 3]));
         assert_eq!(to_value(&foo).unwrap(), rmpv::Value::Ext(42, vec![1, 2, 3]));
+
+        // Without `Bytes`, the data is a sequence of u8s.
+        #[derive(Serialize)]
+        #[serde(rename = "_ExtStruct")]
+        struct Plain((i8, Vec<u8>));
+        #[derive(Serialize)]
+        #[serde(rename = "_ExtStruct")]
+        struct Fixed((i8, [u8; 2]));
+
+        let plain = Plain((-3, vec![1, 2, 3]));
+        assert_eq!(
+            to_value(&plain).unwrap(),
+            rmpv::Value::Ext(-3, vec![1, 2, 3])
+        );
+        let fixed = Fixed((4, [5, 6]));
+        assert_eq!(to_value(&fixed).unwrap(), rmpv::Value::Ext(4, vec![5, 6]));
+        for tag in [-1, i8::MIN, i8::MAX] {
+            let foo = Foo((tag, vec![1]));
+            assert_eq!(to_value(&foo).unwrap(), rmpv::Value::Ext(tag, vec![1]));
+        }
+
+        #[serde_as]
+        #[derive(Serialize)]
+        #[serde(rename = "_ExtStruct")]
+        struct Wide(#[serde_as(as = "(_, Bytes)")] (i64, Vec<u8>));
+
+        #[derive(Serialize)]
+        #[serde(rename = "_ExtStruct")]
+        struct Malformed<T>(T);
+
+        let invalid = |r: RResult<rmpv::Value>| {
+            assert_eq!(&ErrorKind::InvalidExtStruct, r.unwrap_err().kind());
+        };
+        invalid(to_value(&Wide((128, vec![]))));
+        invalid(to_value(&Wide((-129, vec![]))));
+        invalid(to_value(&Malformed((1, vec![256u16]))));
+        invalid(to_value(&Malformed((1, vec![-1i8]))));
+        invalid(to_value(&Malformed((1, vec!["a"]))));
+        invalid(to_value(&Malformed(("a", ()))));
+        invalid(to_value(&Malformed((1,))));
+        invalid(to_value(&Malformed(1)));
+    }
+
+    #[test]
+    fn test_struct_repr() {
+        #[derive(Serialize)]
+        struct S {
+            a: u8,
+            #[serde(skip_serializing_if = "Option::is_none")]
+            b: Option<u8>,
+            c: &'static str,
+        }
+
+        #[derive(Serialize)]
+        enum E {
+            V { a: u8 },
+        }
+
+        let s = S {
+            a: 1,
+            b: None,
+            c: "c",
+        };
+        let config = Config::new().with_struct_repr(StructRepr::Array);
+        assert_eq!(
+            to_value_with(&s, &config).unwrap(),
+            rmpv::Value::Array(vec![1.into(), rmpv::Value::Nil, "c".into()])
+        );
+        assert_eq!(
+            to_value(&s).unwrap(),
+            rmpv::Value::Map(vec![("a".into(), 1.into()), ("c".into(), "c".into())])
+        );
+
+        let config = config.with_enum_repr(EnumRepr::ExternallyTagged);
+        assert_eq!(
+            to_value_with(&E::V { a: 1 }, &config).unwrap(),
+            rmpv::Value::Map(vec![("V".into(), rmpv::Value::Array(vec![1.into()]))])
+        );
+
+        let config = config.with_struct_repr(StructRepr::FieldIndex);
+        assert_eq!(
+            to_value_with(&s, &config).unwrap(),
+            rmpv::Value::Map(vec![(0.into(), 1.into()), (2.into(), "c".into())])
+        );
+        assert_eq!(
+            to_value_with(&E::V { a: 1 }, &config).unwrap(),
+            rmpv::Value::Map(vec![(
+                "V".into(),
+                rmpv::Value::Map(vec![(0.into(), 1.into())])
+            )])
+        );
+    }
+
+    #[test]
+    fn test_presets() {
+        #[derive(Serialize)]
+        enum E {
+            Unit,
+            Struct { a: u8 },
+        }
+
+        #[derive(Serialize)]
+        struct S {
+            e: E,
+            es: Vec<E>,
+        }
+
+        let s = S {
+            e: E::Unit,
+            es: vec![E::Struct { a: 1 }],
+        };
+        let compat = to_value_with(&s, &Config::rmp_serde_compat()).unwrap();
+        assert_eq!(
+            compat,
+            rmpv::Value::Array(vec![
+                "Unit".into(),
+                rmpv::Value::Array(vec![rmpv::Value::Map(vec![(
+                    "Struct".into(),
+                    rmpv::Value::Array(vec![1.into()])
+                )])])
+            ])
+        );
+        let compact = to_value_with(&s, &Config::compact()).unwrap();
+        assert_eq!(
+            compact,
+            rmpv::Value::Array(vec![
+                0.into(),
+                rmpv::Value::Array(vec![rmpv::Value::Map(vec![(
+                    1.into(),
+                    rmpv::Value::Array(vec![1.into()])
+                )])])
+            ])
+        );
+    }
+
+    #[test]
+    fn test_human_readable() {
+        let addr = std::net::Ipv4Addr::LOCALHOST;
+        assert_eq!(
+            to_value(&addr).unwrap(),
+            rmpv::Value::Array(vec![127.into(), 0.into(), 0.into(), 1.into()])
+        );
+        let config = Config::new().with_human_readable(true);
+        assert_eq!(to_value_with(&addr, &config).unwrap(), "127.0.0.1".into());
+    }
+
+    #[test]
+    fn test_enum_repr() {
+        #[derive(Serialize)]
+        enum TEnum {
+            Unit,
+            Tuple(u8, u8),
+            Struct { a: u8 },
+        }
+
+        let check = |repr, v: &TEnum, expected: rmpv::Value| {
+            let config = Config::new().with_enum_repr(repr);
+            assert_eq!(to_value_with(v, &config).unwrap(), expected);
+        };
+
+        check(
+            EnumRepr::ExternallyTagged,
+            &TEnum::Unit,
+            rmpv::Value::from("Unit"),
+        );
+        check(
+            EnumRepr::ExternallyTagged,
+            &TEnum::Tuple(1, 2),
+            rmpv::Value::Map(vec![(
+                rmpv::Value::from("Tuple"),
+                rmpv::Value::Array(vec![rmpv::Value::from(1), rmpv::Value::from(2)]),
+            )]),
+        );
+        check(EnumRepr::VariantIndex, &TEnum::Unit, rmpv::Value::from(0));
+        check(
+            EnumRepr::VariantIndex,
+            &TEnum::Struct { a: 1 },
+            rmpv::Value::Map(vec![(
+                rmpv::Value::from(2),
+                rmpv::Value::Map(vec![(rmpv::Value::from("a"), rmpv::Value::from(1))]),
+            )]),
+        );
+        check(
+            EnumRepr::IndexArray,
+            &TEnum::Unit,
+            rmpv::Value::Array(vec![rmpv::Value::from(0)]),
+        );
+        check(
+            EnumRepr::IndexArray,
+            &TEnum::Tuple(1, 2),
+            rmpv::Value::Array(vec![
+                rmpv::Value::from(1),
+                rmpv::Value::from(1),
+                rmpv::Value::from(2),
+            ]),
+        );
+    }
+
+    #[test]
+    fn test_int128() {
+        assert_eq!(to_value(&-1i128).unwrap(), rmpv::Value::from(-1));
+        assert_eq!(
+            to_value(&u128::from(u64::MAX)).unwrap(),
+            rmpv::Value::from(u64::MAX)
+        );
+        let err = to_value(&i128::MIN).unwrap_err();
+        assert_eq!(
+            &ErrorKind::Int128OutOfRange(i128::MIN.to_string()),
+            err.kind()
+        );
+        let err = to_value(&u128::MAX).unwrap_err();
+        assert_eq!(
+            &ErrorKind::Int128OutOfRange(u128::MAX.to_string()),
+            err.kind()
+        );
+
+        let big = u128::from(u64::MAX) + 1;
+        let config = Config::new().with_int128_fallback(Int128Fallback::Binary);
+        assert_eq!(
+            to_value_with(&big, &config).unwrap(),
+            rmpv::Value::Binary(big.to_be_bytes().to_vec())
+        );
+        let config = Config::new().with_int128_fallback(Int128Fallback::Ext(9));
+        assert_eq!(
+            to_value_with(&big, &config).unwrap(),
+            rmpv::Value::Ext(9, big.to_be_bytes().to_vec())
+        );
     }
 
     #[test]
@@ -0,0 +1,458 @@
+//! The MessagePack timestamp extension type.
+//!
+//! [`Timestamp`] serializes to `Value::Ext(-1, ..)` using the smallest of the
+//! timestamp32, timestamp64 and timestamp96 encodings that can hold it. With
+//! the `chrono`, `time` or `jiff` features enabled, the `chrono`, `time` and
+//! `jiff` submodules can be used with `#[serde(with = "...")]` to write those
+//! crates' datetime types as timestamps.
+
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
+
+use crate::{
+    error::{Error, ErrorKind, Unexpected},
+    ext,
+};
+
+/// The Ext tag of the MessagePack timestamp type.
+pub const TIMESTAMP_EXT_TAG: i8 = -1;
+
+const NANOS_PER_SEC: u32 = 1_000_000_000;
+
+/// A point in time as seconds and nanoseconds since the Unix epoch, stored as
+/// a MessagePack timestamp.
+///
+/// # Example
+/// ```rust
+/// use serde_rmpv::Timestamp;
+///
+/// let ts = Timestamp::new(1, 500).unwrap();
+/// let value = serde_rmpv::to_value(&ts).unwrap();
+/// assert_eq!(ts, serde_rmpv::from_value(&value).unwrap());
+/// ```
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct Timestamp {
+    seconds: i64,
+    nanoseconds: u32,
+}
+
+impl Timestamp {
+    /// Creates a timestamp, or returns `None` if `nanoseconds` is not less
+    /// than one second.
+    pub fn new(seconds: i64, nanoseconds: u32) -> Option<Self> {
+        (nanoseconds < NANOS_PER_SEC).then_some(Timestamp {
+            seconds,
+            nanoseconds,
+        })
+    }
+
+    /// Whole seconds since the Unix epoch.
+    pub fn seconds(&self) -> i64 {
+        self.seconds
+    }
+
+    /// Nanoseconds past the second, always less than one billion.
+    pub fn nanoseconds(&self) -> u32 {
+        self.nanoseconds
+    }
+
+    /// Encodes the timestamp as Ext data, using the smallest encoding that
+    /// can hold it.
+    pub fn to_bytes(&self) -> Vec<u8> {
+        if self.seconds >> 34 == 0 {
+            let data = (u64::from(self.nanoseconds) << 34) | self.seconds as u64;
+            if data >> 32 == 0 {
+                // timestamp32: seconds in a u32
+                (data as u32).to_be_bytes().to_vec()
+            } else {
+                // timestamp64: 30 bits of nanoseconds, 34 bits of seconds
+                data.to_be_bytes().to_vec()
+            }
+        } else {
+            // timestamp96: u32 nanoseconds, i64 seconds
+            let mut buf = Vec::with_capacity(12);
+            buf.extend_from_slice(&self.nanoseconds.to_be_bytes());
+            buf.extend_from_slice(&self.seconds.to_be_bytes());
+            buf
+        }
+    }
+
+    /// Decodes a timestamp from Ext data in any of the three encodings.
+    ///
+    /// # Errors
+    /// Returns an error if the data has the wrong length, or the nanoseconds
+    /// are out of range.
+    pub fn from_bytes(data: &[u8]) -> Result<Self, Error> {
+        let (seconds, nanoseconds) = match *data {
+            [a, b, c, d] => (i64::from(u32::from_be_bytes([a, b, c, d])), 0),
+            [a, b, c, d, e, f, g, h] => {
+                let data = u64::from_be_bytes([a, b, c, d, e, f, g, h]);
+                ((data & 0x3_ffff_ffff) as i64, (data >> 34) as u32)
+            }
+            [a, b, c, d, e, f, g, h, i, j, k, l] => (
+                i64::from_be_bytes([e, f, g, h, i, j, k, l]),
+                u32::from_be_bytes([a, b, c, d]),
+            ),
+            _ => return Err(invalid_length(data.len())),
+        };
+        Timestamp::new(seconds, nanoseconds).ok_or_else(|| {
+            ErrorKind::InvalidValue {
+                unexpected: Unexpected::Unsigned(nanoseconds.into()),
+                expected: "nanoseconds less than one second".to_string(),
+            }
+            .into()
+        })
+    }
+}
+
+fn invalid_length(len: usize) -> Error {
+    ErrorKind::InvalidLength {
+        len,
+        expected: "timestamp data of 4, 8 or 12 bytes".to_string(),
+    }
+    .into()
+}
+
+fn out_of_range() -> Error {
+    ErrorKind::Message("timestamp out of range".to_string()).into()
+}
+
+impl TryFrom<SystemTime> for Timestamp {
+    type Error = Error;
+
+    fn try_from(time: SystemTime) -> Result<Self, Error> {
+        match time.duration_since(UNIX_EPOCH) {
+            Ok(d) => Ok(Timestamp {
+                seconds: i64::try_from(d.as_secs()).map_err(|_| out_of_range())?,
+                nanoseconds: d.subsec_nanos(),
+            }),
+            Err(e) => {
+                // Before the epoch, so count back and then forward into the
+                // second.
+                let d = e.duration();
+                let seconds = i64::try_from(d.as_secs()).map_err(|_| out_of_range())?;
+                Ok(match d.subsec_nanos() {
+                    0 => Timestamp {
+                        seconds: -seconds,
+                        nanoseconds: 0,
+                    },
+                    n => Timestamp {
+                        seconds: -seconds - 1,
+                        nanoseconds: NANOS_PER_SEC - n,
+                    },
+                })
+            }
+        }
+    }
+}
+
+impl TryFrom<Timestamp> for SystemTime {
+    type Error = Error;
+
+    fn try_from(ts: Timestamp) -> Result<Self, Error> {
+        let time = if ts.seconds >= 0 {
+            UNIX_EPOCH.checked_add(Duration::from_secs(ts.seconds as u64))
+        } else {
+            UNIX_EPOCH.checked_sub(Duration::from_secs(ts.seconds.unsigned_abs()))
+        };
+        time.and_then(|t| t.checked_add(Duration::from_nanos(ts.nanoseconds.into())))
+            .ok_or_else(out_of_range)
+    }
+}
+
+impl Serialize for Timestamp {
+    fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
+    where
+        S: Serializer,
+    {
+        ext::serialize_ext(serializer, TIMESTAMP_EXT_TAG, &self.to_bytes())
+    }
+}
+
+impl<'de> Deserialize<'de> for Timestamp {
+    fn deserialize<D>(deserializer: D) -> Result<Self, D::Error>
+    where
+        D: Deserializer<'de>,
+    {
+        let data = ext::deserialize_ext_data(deserializer, TIMESTAMP_EXT_TAG)?;
+        Timestamp::from_bytes(&data).map_err(Error::into_de_error)
+    }
+}
+
+/// Serialize `chrono::DateTime<Utc>` as a MessagePack timestamp, with
+/// `#[serde(with = "serde_rmpv::timestamp::chrono")]`.
+#[cfg(feature = "chrono")]
+pub mod chrono {
+    use ::chrono::{DateTime, Utc};
+    use serde::{de, Deserialize, Deserializer, Serialize, Serializer};
+
+    use super::Timestamp;
+
+    impl From<DateTime<Utc>> for Timestamp {
+        fn from(dt: DateTime<Utc>) -> Self {
+            // chrono represents a leap second as nanoseconds past one second,
+            // which we fold into the next second.
+            let (seconds, nanos) = (dt.timestamp(), dt.timestamp_subsec_nanos());
+            Timestamp {
+                seconds: seconds + i64::from(nanos / super::NANOS_PER_SEC),
+                nanoseconds: nanos % super::NANOS_PER_SEC,
+            }
+        }
+    }
+
+    impl TryFrom<Timestamp> for DateTime<Utc> {
+        type Error = crate::Error;
+
+        fn try_from(ts: Timestamp) -> Result<Self, crate::Error> {
+            DateTime::from_timestamp(ts.seconds, ts.nanoseconds).ok_or_else(super::out_of_range)
+        }
+    }
+
+    pub fn serialize<S>(dt: &DateTime<Utc>, serializer: S) -> Result<S::Ok, S::Error>
+    where
+        S: Serializer,
+    {
+        Timestamp::from(*dt).serialize(serializer)
+    }
+
+    pub fn deserialize<'de, D>(deserializer: D) -> Result<DateTime<Utc>, D::Error>
+    where
+        D: Deserializer<'de>,
+    {
+        Timestamp::deserialize(deserializer)?
+            .try_into()
+            .map_err(de::Error::custom)
+    }
+}
+
+/// Serialize `time::OffsetDateTime` as a MessagePack timestamp, with
+/// `#[serde(with = "serde_rmpv::timestamp::time")]`. The offset is not
+/// preserved; values are read back in UTC.
+#[cfg(feature = "time")]
+pub mod time {
+    use ::time::OffsetDateTime;
+    use serde::{de, Deserialize, Deserializer, Serialize, Serializer};
+
+    use super::Timestamp;
+
+    impl From<OffsetDateTime> for Timestamp {
+        fn from(dt: OffsetDateTime) -> Self {
+            Timestamp {
+                seconds: dt.unix_timestamp(),
+                nanoseconds: dt.nanosecond(),
+            }
+        }
+    }
+
+    impl TryFrom<Timestamp> for OffsetDateTime {
+        type Error = crate::Error;
+
+        fn try_from(ts: Timestamp) -> Result<Self, crate::Error> {
+            let nanos = i128::from(ts.seconds) * 1_000_000_000 + i128::from(ts.nanoseconds);
+            OffsetDateTime::from_unix_timestamp_nanos(nanos).map_err(|_| super::out_of_range())
+        }
+    }
+
+    pub fn serialize<S>(dt: &OffsetDateTime, serializer: S) -> Result<S::Ok, S::Error>
+    where
+        S: Serializer,
+    {
+        Timestamp::from(*dt).serialize(serializer)
+    }
+
+    pub fn deserialize<'de, D>(deserializer: D) -> Result<OffsetDateTime, D::Error>
+    where
+        D: Deserializer<'de>,
+    {
+        Timestamp::deserialize(deserializer)?
+            .try_into()
+            .map_err(de::Error::custom)
+    }
+}
+
+/// Serialize `jiff::Timestamp` as a MessagePack timestamp, with
+/// `#[serde(with = "serde_rmpv::timestamp::jiff")]`.
+#[cfg(feature = "jiff")]
+pub mod jiff {
+    use serde::{de, Deserialize, Deserializer, Serialize, Serializer};
+
+    use super::Timestamp;
+
+    impl From<::jiff::Timestamp> for Timestamp {
+        fn from(ts: ::jiff::Timestamp) -> Self {
+            // jiff counts nanoseconds towards zero, so negative fractions
+            // borrow from the seconds.
+            let (seconds, nanos) = (ts.as_second(), ts.subsec_nanosecond());
+            if nanos < 0 {
+                Timestamp {
+                    seconds: seconds - 1,
+                    nanoseconds: (nanos + 1_000_000_000) as u32,
+                }
+            } else {
+                Timestamp {
+                    seconds,
+                    nanoseconds: nanos as u32,
+                }
+            }
+        }
+    }
+
+    impl TryFrom<Timestamp> for ::jiff::Timestamp {
+        type Error = crate::Error;
+
+        fn try_from(ts: Timestamp) -> Result<Self, crate::Error> {
+            let nanos = i128::from(ts.seconds) * 1_000_000_000 + i128::from(ts.nanoseconds);
+            ::jiff::Timestamp::from_nanosecond(nanos).map_err(|_| super::out_of_range())
+        }
+    }
+
+    pub fn serialize<S>(ts: &::jiff::Timestamp, serializer: S) -> Result<S::Ok, S::Error>
+    where
+        S: Serializer,
+    {
+        Timestamp::from(*ts).serialize(serializer)
+    }
+
+    pub fn deserialize<'de, D>(deserializer: D) -> Result<::jiff::Timestamp, D::Error>
+    where
+        D: Deserializer<'de>,
+    {
+        Timestamp::deserialize(deserializer)?
+            .try_into()
+            .map_err(de::Error::custom)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use crate::{from_value, to_value};
+
+    #[test]
+    fn test_encodings() {
+        for (ts, len) in [
+            (Timestamp::new(0, 0), 4),
+            (Timestamp::new(u32::MAX.into(), 0), 4),
+            (Timestamp::new(1, 1), 8),
+            (Timestamp::new(u32::MAX as i64 + 1, 0), 8),
+            (Timestamp::new((1 << 34) - 1, 999_999_999), 8),
+            (Timestamp::new(1 << 34, 0), 12),
+            (Timestamp::new(-1, 500), 12),
+            (Timestamp::new(i64::MIN, 999_999_999), 12),
+        ] {
+            let ts = ts.unwrap();
+            let val = to_value(&ts).unwrap();
+            match &val {
+                rmpv::Value::Ext(TIMESTAMP_EXT_TAG, data) => assert_eq!(len, data.len()),
+                other => panic!("expected timestamp ext: {}", other),
+            }
+            assert_eq!(ts, from_value(&val).unwrap());
+        }
+
+        assert_eq!(None, Timestamp::new(0, NANOS_PER_SEC));
+        for len in [0, 5, 9, 13] {
+            Timestamp::from_bytes(&vec![0; len]).expect_err("expected length error");
+        }
+        Timestamp::from_bytes(&[0xff; 8]).expect_err("expected nanosecond error");
+        from_value::<Timestamp>(&rmpv::Value::Ext(1, vec![0; 4])).expect_err("expected tag error");
+
+        // Invalid data keeps its error kind through the deserializer.
+        let val = rmpv::Value::Ext(TIMESTAMP_EXT_TAG, vec![0; 5]);
+        let err = from_value::<Timestamp>(&val).unwrap_err();
+        assert!(matches!(
+            err.kind(),
+            ErrorKind::InvalidLength { len: 5, .. }
+        ));
+        let val = rmpv::Value::Ext(TIMESTAMP_EXT_TAG, vec![0xff; 8]);
+        let err = from_value::<Timestamp>(&val).unwrap_err();
+        assert!(matches!(err.kind(), ErrorKind::InvalidValue { .. }));
+    }
+
+    #[test]
+    fn test_system_time() {
+        for ts in [
+            Timestamp::new(0, 0),
+            Timestamp::new(1_700_000_000, 123),
+            Timestamp::new(-1, 0),
+            Timestamp::new(-2, 999_999_999),
+        ] {
+            let ts = ts.unwrap();
+            let time = SystemTime::try_from(ts).unwrap();
+            assert_eq!(ts, Timestamp::try_from(time).unwrap());
+        }
+        assert_eq!(
+            Timestamp::new(-1, 999_999_999).unwrap(),
+            Timestamp::try_from(UNIX_EPOCH - Duration::from_nanos(1)).unwrap()
+        );
+    }
+
+    #[cfg(feature = "chrono")]
+    #[test]
+    fn test_chrono() {
+        use ::chrono::{DateTime, Utc};
+        use serde_derive::{Deserialize, Serialize};
+
+        #[derive(Serialize, Deserialize, Debug, PartialEq)]
+        struct S {
+            #[serde(with = "super::chrono")]
+            at: DateTime<Utc>,
+        }
+
+        let s = S {
+            at: DateTime::from_timestamp(-5, 7).unwrap(),
+        };
+        let val = to_value(&s).unwrap();
+        assert_eq!(
+            Timestamp::new(-5, 7).unwrap(),
+            from_value(&val["at"]).unwrap()
+        );
+        assert_eq!(s, from_value(&val).unwrap());
+    }
+
+    #[cfg(feature = "time")]
+    #[test]
+    fn test_time() {
+        use ::time::OffsetDateTime;
+        use serde_derive::{Deserialize, Serialize};
+
+        #[derive(Serialize, Deserialize, Debug, PartialEq)]
+        struct S {
+            #[serde(with = "super::time")]
+            at: OffsetDateTime,
+        }
+
+        let s = S {
+            at: OffsetDateTime::from_unix_timestamp_nanos(-1_500_000_000).unwrap(),
+        };
+        let val = to_value(&s).unwrap();
+        assert_eq!(
+            Timestamp::new(-2, 500_000_000).unwrap(),
+            from_value(&val["at"]).unwrap()
+        );
+        assert_eq!(s, from_value(&val).unwrap());
+    }
+
+    #[cfg(feature = "jiff")]
+    #[test]
+    fn test_jiff() {
+        use serde_derive::{Deserialize, Serialize};
+
+        #[derive(Serialize, Deserialize, Debug, PartialEq)]
+        struct S {
+            #[serde(with = "super::jiff")]
+            at: ::jiff::Timestamp,
+        }
+
+        let s = S {
+            at: ::jiff::Timestamp::from_nanosecond(-1_500_000_000).unwrap(),
+        };
+        let val = to_value(&s).unwrap();
+        assert_eq!(
+            Timestamp::new(-2, 500_000_000).unwrap(),
+            from_value(&val["at"]).unwrap()
+        );
+        assert_eq!(s, from_value(&val).unwrap());
+    }
+}
@@ -0,0 +1,124 @@
+//! Pass-through of borrowed [`rmpv::ValueRef`] fields.
+//!
+//! rmpv implements `Serialize` and `Deserialize` for [`rmpv::Value`], so an
+//! embedded `Value` field needs no annotation. [`rmpv::ValueRef`] only
+//! implements `Deserialize<'de>` for `ValueRef<'de>`, which can't borrow from
+//! the input when nested in a struct. Fields of that type use this module
+//! instead:
+//!
+//! ```rust
+//! use serde_derive::{Deserialize, Serialize};
+//!
+//! #[derive(Serialize, Deserialize)]
+//! struct Request<'a> {
+//!     method: &'a str,
+//!     #[serde(with = "serde_rmpv::value_ref", borrow)]
+//!     params: rmpv::ValueRef<'a>,
+//! }
+//!
+//! let val = serde_rmpv::to_value(&Request {
+//!     method: "add",
+//!     params: rmpv::ValueRef::from(1),
+//! })
+//! .unwrap();
+//! let req: Request = serde_rmpv::from_value(&val).unwrap();
+//! assert_eq!(rmpv::ValueRef::from(1), req.params);
+//! ```
+//!
+//! Deserializing borrows strings, binary and Ext data from the input, so it
+//! works with [`from_value`](crate::from_value) but not the owned variants.
+
+use rmpv::ValueRef;
+use serde::{
+    ser::{SerializeMap, SerializeSeq},
+    Deserialize, Deserializer, Serialize, Serializer,
+};
+
+use crate::ext;
+
+/// Serializes a [`ValueRef`] the way rmpv serializes the equivalent
+/// [`rmpv::Value`].
+pub fn serialize<S>(value: &ValueRef<'_>, serializer: S) -> Result<S::Ok, S::Error>
+where
+    S: Serializer,
+{
+    Ref(value).serialize(serializer)
+}
+
+/// Deserializes a [`ValueRef`] borrowing from the input.
+pub fn deserialize<'de: 'a, 'a, D>(deserializer: D) -> Result<ValueRef<'a>, D::Error>
+where
+    D: Deserializer<'de>,
+{
+    ValueRef::<'de>::deserialize(deserializer)
+}
+
+struct Ref<'r, 'a>(&'r ValueRef<'a>);
+
+impl Serialize for Ref<'_, '_> {
+    fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
+    where
+        S: Serializer,
+    {
+        match self.0 {
+            ValueRef::Nil => serializer.serialize_unit(),
+            ValueRef::Boolean(v) => serializer.serialize_bool(*v),
+            ValueRef::Integer(i) => match (i.as_u64(), i.as_i64()) {
+                (Some(v), _) => serializer.serialize_u64(v),
+                (None, Some(v)) => serializer.serialize_i64(v),
+                (None, None) => unreachable!("msgpack integers fit in u64 or i64"),
+            },
+            ValueRef::F32(v) => serializer.serialize_f32(*v),
+            ValueRef::F64(v) => serializer.serialize_f64(*v),
+            ValueRef::String(s) => match s.as_str() {
+                Some(s) => serializer.serialize_str(s),
+                None => serializer.serialize_bytes(s.as_bytes()),
+            },
+            ValueRef::Binary(data) => serializer.serialize_bytes(data),
+            ValueRef::Array(items) => {
+                let mut seq = serializer.serialize_seq(Some(items.len()))?;
+                for item in items {
+                    seq.serialize_element(&Ref(item))?;
+                }
+                seq.end()
+            }
+            ValueRef::Map(entries) => {
+                let mut map = serializer.serialize_map(Some(entries.len()))?;
+                for (k, v) in entries {
+                    map.serialize_entry(&Ref(k), &Ref(v))?;
+                }
+                map.end()
+            }
+            ValueRef::Ext(tag, data) => ext::serialize_ext(serializer, *tag, data),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_derive::{Deserialize, Serialize};
+
+    use crate::{from_value, to_value};
+
+    #[test]
+    fn test_value_ref() {
+        #[derive(Serialize, Deserialize, Debug, PartialEq)]
+        struct Msg<'a> {
+            #[serde(with = "super", borrow)]
+            payload: rmpv::ValueRef<'a>,
+        }
+
+        let payload = rmpv::Value::Array(vec![
+            rmpv::Value::F32(1.5),
+            rmpv::Value::from(-1),
+            rmpv::Value::Binary(vec![1]),
+            rmpv::Value::Map(vec![(rmpv::Value::Nil, rmpv::Value::Ext(-2, vec![3]))]),
+        ]);
+        let msg = Msg {
+            payload: payload.as_ref(),
+        };
+        let val = to_value(&msg).unwrap();
+        assert_eq!(Some(&payload), val.as_map().map(|m| &m[0].1));
+        assert_eq!(msg, from_value(&val).unwrap());
+    }
+}